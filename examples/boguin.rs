@@ -5,14 +5,14 @@ extern crate http_with_url as http;
 #[macro_use]
 extern crate log;
 
-use std::error::Error;
-use std::io::{self, Read, Write};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
 
-use boguin::Client;
+use boguin::{Body, Client, CookieJar, Netrc, Proxy, StreamBody};
 use clap::{App, Arg, ArgMatches};
 use http::{Request, Response, Url};
 
-fn build_request(matches: ArgMatches) -> Result<Request<Vec<u8>>, String> {
+fn build_request(matches: &ArgMatches) -> Result<Request<StreamBody<Box<dyn Read>>>, String> {
     let url = Url::parse(matches.value_of("url").expect("url is present"))
         .map_err(|_| "Invalid URL".to_owned())?;
     let mut request = Request::builder(url);
@@ -36,17 +36,19 @@ fn build_request(matches: ArgMatches) -> Result<Request<Vec<u8>>, String> {
     }
 
     if matches.is_present("stdin") {
-        let mut data = Vec::new();
-        io::stdin()
-            .read_to_end(&mut data)
-            .map_err(|e| e.to_string())?;
-        return request.body(data).map_err(|e| e.description().to_owned());
+        let body: Box<dyn Read> = Box::new(io::stdin());
+        return request
+            .body(StreamBody::new(body))
+            .map_err(|e| e.to_string());
     }
 
-    return request.body(Vec::new()).map_err(|e| e.to_string());
+    let body: Box<dyn Read> = Box::new(io::empty());
+    request
+        .body(StreamBody::with_length(body, 0))
+        .map_err(|e| e.to_string())
 }
 
-fn print_response(response: &Response<Vec<u8>>) {
+fn print_response<R: Read>(response: &mut Response<Body<R>>) -> io::Result<()> {
     // Note: Writes to stderr always succeed.
     let mut stderr = io::stderr();
     writeln!(stderr, "{:?} {}", response.version(), response.status()).unwrap();
@@ -54,9 +56,27 @@ fn print_response(response: &Response<Vec<u8>>) {
         let value = value.to_str().unwrap_or("<binary value>");
         writeln!(stderr, "{}: {}", key.as_str(), value).unwrap();
     }
-    writeln!(stderr, "").unwrap();
-    let mut stdout = io::stdout();
-    stdout.write_all(response.body()).unwrap();
+    writeln!(stderr).unwrap();
+    let stdout = io::stdout();
+    io::copy(response.body_mut(), &mut stdout.lock())?;
+    Ok(())
+}
+
+fn load_cookies(path: &str, keep_session: bool) -> io::Result<CookieJar> {
+    let mut jar = CookieJar::new();
+    match File::open(path) {
+        Ok(file) => jar.load_netscape(BufReader::new(file), keep_session)?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+    Ok(jar)
+}
+
+fn save_cookies(path: &str, jar: &CookieJar, keep_session: bool) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    jar.save_netscape(&mut writer, keep_session)?;
+    // Dropping the writer would ignore errors while writing the buffer.
+    writer.flush()
 }
 
 fn main() {
@@ -90,21 +110,98 @@ fn main() {
                 .long("stdin")
                 .help("Read a HTTP body from standard input"),
         )
+        .arg(
+            Arg::with_name("cookie-jar")
+                .long("cookie-jar")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Read cookies from and save cookies to a cookies.txt file"),
+        )
+        .arg(
+            Arg::with_name("keep-session-cookies")
+                .long("keep-session-cookies")
+                .requires("cookie-jar")
+                .help("Save and load session cookies in the cookie jar"),
+        )
+        .arg(
+            Arg::with_name("netrc")
+                .long("netrc")
+                .help("Use credentials from ~/.netrc when a server requests them"),
+        )
+        .arg(
+            Arg::with_name("proxy")
+                .long("proxy")
+                .value_name("URL")
+                .takes_value(true)
+                .help("Send all requests through a HTTP or SOCKS proxy"),
+        )
+        .arg(
+            Arg::with_name("proxy-env")
+                .long("proxy-env")
+                .help("Use proxies from http_proxy, https_proxy, all_proxy and no_proxy"),
+        )
         .get_matches();
-    let request = match build_request(matches) {
+    let request = match build_request(&matches) {
         Ok(request) => request,
         Err(e) => {
             error!("{}", e);
             return;
         }
     };
-    let mut client = Client::new();
-    let response: Response<Vec<u8>> = match client.fetch(request) {
+    let keep_session = matches.is_present("keep-session-cookies");
+    let mut builder = Client::builder();
+    if let Some(path) = matches.value_of("cookie-jar") {
+        match load_cookies(path, keep_session) {
+            Ok(jar) => {
+                builder.cookie_jar(jar);
+            }
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+    }
+    if matches.is_present("netrc") {
+        match Netrc::from_home() {
+            Ok(netrc) => {
+                builder.netrc(Some(netrc));
+            }
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+    }
+    builder.proxy_from_env(matches.is_present("proxy-env"));
+    if let Some(url) = matches.value_of("proxy") {
+        let proxy = Url::parse(url)
+            .map_err(|e| e.to_string())
+            .and_then(|url| Proxy::all(url).map_err(|e| e.to_string()));
+        match proxy {
+            Ok(proxy) => {
+                builder.proxy(proxy);
+            }
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+    }
+    let client = builder.build();
+    let mut response = match client.send(request) {
         Ok(response) => response,
         Err(e) => {
             error!("{}", e);
             return;
         }
     };
-    print_response(&response);
+    if let Err(e) = print_response(&mut response) {
+        error!("{}", e);
+    }
+    drop(response);
+    if let (Some(path), Some(jar)) = (matches.value_of("cookie-jar"), client.cookie_jar()) {
+        if let Err(e) = save_cookies(path, &jar, keep_session) {
+            error!("{}", e);
+        }
+    }
 }
@@ -2,7 +2,7 @@ extern crate boguin;
 extern crate http_with_url as http;
 
 fn main() {
-    let mut client = boguin::Client::new();
+    let client = boguin::Client::new();
     let url = http::Url::parse("https://httpbin.org/status/418").unwrap();
     let request = http::Request::new(url, ());
     let response: http::Response<String> = client.fetch(request).expect("request works");
@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::process;
+use std::time::SystemTime;
+
+use http::{Method, Url};
+use http::header::{self, HeaderMap, HeaderValue};
+use http::url::Origin;
+use md5;
+use sha2::{Digest, Sha256};
+
+use netrc::Netrc;
+use util::{basic_auth, hex};
+
+/// Credentials to authenticate with a server.
+///
+/// Basic and Bearer credentials are sent with every request to their
+/// origin. Digest credentials are used once the server responds with
+/// `401 Unauthorized` and a `WWW-Authenticate: Digest` challenge,
+/// the request is then sent again. Later requests to the same origin
+/// reuse the nonce of the challenge.
+///
+/// See also `Netrc` for credentials read from a file.
+#[derive(Clone)]
+pub struct Credentials {
+    inner: Kind,
+}
+
+#[derive(Clone)]
+enum Kind {
+    Header(HeaderValue),
+    Digest { username: String, password: String },
+}
+
+impl Credentials {
+    /// Basic authentication with a username and password.
+    pub fn basic(username: &str, password: &str) -> Credentials {
+        Credentials {
+            inner: Kind::Header(basic_auth(username, password)),
+        }
+    }
+
+    /// Bearer authentication with a token.
+    ///
+    /// Fails if the token contains characters not allowed in a header.
+    pub fn bearer(token: &str) -> io::Result<Credentials> {
+        let value = HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        Ok(Credentials {
+            inner: Kind::Header(value),
+        })
+    }
+
+    /// Digest authentication with a username and password.
+    ///
+    /// The algorithms MD5 and SHA-256 are supported with and without
+    /// `-sess` and with the quality of protection `auth`.
+    pub fn digest(username: &str, password: &str) -> Credentials {
+        Credentials {
+            inner: Kind::Digest {
+                username: username.to_string(),
+                password: password.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print secrets.
+        match self.inner {
+            Kind::Header(_) => f.write_str("Header"),
+            Kind::Digest { ref username, .. } => {
+                f.debug_struct("Digest").field("username", username).finish()
+            }
+        }
+    }
+}
+
+/// Credentials of a client by origin and the state of Digest authentication.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Authenticator {
+    credentials: Vec<(Origin, Credentials)>,
+    sessions: HashMap<Origin, DigestSession>,
+    netrc: Option<Netrc>,
+}
+
+impl Authenticator {
+    /// Uses `credentials` for all requests to `origin`.
+    pub(crate) fn add(&mut self, origin: Origin, credentials: Credentials) {
+        self.credentials.retain(|(o, _)| *o != origin);
+        self.credentials.push((origin, credentials));
+    }
+
+    /// Uses credentials from `netrc` for servers requesting basic authentication.
+    pub(crate) fn set_netrc(&mut self, netrc: Option<Netrc>) {
+        self.netrc = netrc;
+    }
+
+    fn find(&self, origin: &Origin) -> Option<&Credentials> {
+        self.credentials
+            .iter()
+            .find(|(o, _)| o == origin)
+            .map(|(_, credentials)| credentials)
+    }
+
+    /// Returns the `Authorization` header for a request.
+    pub(crate) fn authorization(&mut self, method: &Method, url: &Url) -> Option<HeaderValue> {
+        let origin = url.origin();
+        let (username, password) = match self.find(&origin)?.inner {
+            Kind::Header(ref value) => return Some(value.clone()),
+            Kind::Digest {
+                ref username,
+                ref password,
+            } => (username.clone(), password.clone()),
+        };
+        let session = self.sessions.get_mut(&origin)?;
+        session.authorization(&username, &password, method, url)
+    }
+
+    /// Handles the challenges of a `401 Unauthorized` response.
+    ///
+    /// Returns true if the request should be sent again.
+    pub(crate) fn challenge(&mut self, url: &Url, headers: &HeaderMap) -> bool {
+        let origin = url.origin();
+        match self.find(&origin) {
+            Some(&Credentials {
+                inner: Kind::Digest { .. },
+            }) => {}
+            Some(_) => return false,
+            None => return self.challenge_netrc(url, headers),
+        }
+        let session = challenges(headers)
+            .into_iter()
+            .filter(|(scheme, _)| scheme == "digest")
+            .filter_map(|(_, params)| DigestSession::new(&params))
+            .max_by_key(|session| session.algorithm);
+        if let Some(session) = session {
+            debug!("Received Digest challenge for {:?}", session.realm);
+            self.sessions.insert(origin, session);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Adds basic credentials for the origin of `url` from the netrc file
+    /// if the server requests basic authentication.
+    fn challenge_netrc(&mut self, url: &Url, headers: &HeaderMap) -> bool {
+        if !challenges(headers).iter().any(|(scheme, _)| scheme == "basic") {
+            return false;
+        }
+        let credentials = match (url.host_str(), self.netrc.as_ref()) {
+            (Some(host), Some(netrc)) => match netrc.credentials(host) {
+                Some((login, password)) => Credentials::basic(login, password),
+                None => return false,
+            },
+            _ => return false,
+        };
+        debug!("Using credentials from netrc for {}", url.origin().ascii_serialization());
+        self.add(url.origin(), credentials);
+        true
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+impl Algorithm {
+    fn hash(self, data: &str) -> String {
+        match self {
+            Algorithm::Md5 => format!("{:x}", md5::compute(data)),
+            Algorithm::Sha256 => hex(&Sha256::digest(data.as_bytes())),
+        }
+    }
+}
+
+/// A Digest challenge and the number of requests sent with its nonce.
+// https://httpwg.org/specs/rfc7616.html
+#[derive(Clone, Debug)]
+struct DigestSession {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: Algorithm,
+    name: String,
+    sess: bool,
+    qop: bool,
+    nc: u32,
+}
+
+impl DigestSession {
+    /// Returns `None` if the challenge requires an unsupported feature.
+    fn new(params: &HashMap<String, String>) -> Option<DigestSession> {
+        let name = params.get("algorithm").map_or("MD5", |a| &a[..]);
+        let (algorithm, sess) = match &name.to_ascii_uppercase()[..] {
+            "MD5" => (Algorithm::Md5, false),
+            "MD5-SESS" => (Algorithm::Md5, true),
+            "SHA-256" => (Algorithm::Sha256, false),
+            "SHA-256-SESS" => (Algorithm::Sha256, true),
+            _ => return None,
+        };
+        let qop = match params.get("qop") {
+            Some(qop) => {
+                if !qop.split(',').any(|q| q.trim() == "auth") {
+                    return None;
+                }
+                true
+            }
+            None => false,
+        };
+        Some(DigestSession {
+            realm: params.get("realm")?.clone(),
+            nonce: params.get("nonce")?.clone(),
+            opaque: params.get("opaque").cloned(),
+            algorithm,
+            name: name.to_string(),
+            sess,
+            qop,
+            nc: 0,
+        })
+    }
+
+    fn authorization(
+        &mut self,
+        username: &str,
+        password: &str,
+        method: &Method,
+        url: &Url,
+    ) -> Option<HeaderValue> {
+        self.nc += 1;
+        let cnonce = self.algorithm
+            .hash(&format!("{:?}:{}:{}", SystemTime::now(), process::id(), self.nc));
+        self.response(username, password, method, url, &cnonce)
+    }
+
+    /// Returns the `Authorization` header for the current nonce count.
+    fn response(
+        &self,
+        username: &str,
+        password: &str,
+        method: &Method,
+        url: &Url,
+        cnonce: &str,
+    ) -> Option<HeaderValue> {
+        let nc = format!("{:08x}", self.nc);
+        let uri = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let algorithm = self.algorithm;
+        let h = |data: &str| algorithm.hash(data);
+        let mut ha1 = h(&format!("{}:{}:{}", username, self.realm, password));
+        if self.sess {
+            ha1 = h(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = h(&format!("{}:{}", method, uri));
+        let response = if self.qop {
+            h(&format!("{}:{}:{}:{}:auth:{}", ha1, self.nonce, nc, cnonce, ha2))
+        } else {
+            h(&format!("{}:{}:{}", ha1, self.nonce, ha2))
+        };
+
+        let mut value = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", \
+             algorithm={}, response=\"{}\"",
+            quote(username),
+            quote(&self.realm),
+            quote(&self.nonce),
+            quote(&uri),
+            self.name,
+            response
+        );
+        if self.qop {
+            value.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, cnonce));
+        }
+        if let Some(ref opaque) = self.opaque {
+            value.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+        // Fails for usernames that are not valid in a header.
+        HeaderValue::from_str(&value).ok()
+    }
+}
+
+/// Returns the challenges of all `WWW-Authenticate` headers.
+fn challenges(headers: &HeaderMap) -> Vec<(String, HashMap<String, String>)> {
+    headers
+        .get_all(header::WWW_AUTHENTICATE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(parse_challenges)
+        .collect()
+}
+
+/// Parses the challenges of a `WWW-Authenticate` header.
+///
+/// Returns the lowercase scheme and the parameters of each challenge.
+fn parse_challenges(value: &str) -> Vec<(String, HashMap<String, String>)> {
+    let mut challenges: Vec<(String, HashMap<String, String>)> = Vec::new();
+    let mut rest = value;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        if rest.is_empty() {
+            return challenges;
+        }
+        let end = rest.find(|c: char| c == '=' || c == ',' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let token = &rest[..end];
+        rest = rest[end..].trim_start();
+        if rest.starts_with('=') {
+            let (value, remaining) = parse_param_value(rest[1..].trim_start());
+            rest = remaining;
+            if let Some(&mut (_, ref mut params)) = challenges.last_mut() {
+                params.insert(token.to_ascii_lowercase(), value);
+            }
+        } else {
+            challenges.push((token.to_ascii_lowercase(), HashMap::new()));
+        }
+    }
+}
+
+/// Parses a token or a quoted string and returns the remaining input.
+fn parse_param_value(input: &str) -> (String, &str) {
+    if !input.starts_with('"') {
+        let end = input
+            .find(|c: char| c == ',' || c.is_whitespace())
+            .unwrap_or(input.len());
+        return (input[..end].to_string(), &input[end..]);
+    }
+    let mut value = String::new();
+    let mut escaped = false;
+    for (i, c) in input.char_indices().skip(1) {
+        if escaped {
+            value.push(c);
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            return (value, &input[i + 1..]);
+        } else {
+            value.push(c);
+        }
+    }
+    // Unterminated quoted string.
+    (value, "")
+}
+
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(challenge: &str) -> DigestSession {
+        let (_, params) = parse_challenges(challenge).pop().unwrap();
+        let mut session = DigestSession::new(&params).unwrap();
+        session.nc = 1;
+        session
+    }
+
+    fn response(session: &DigestSession, password: &str, cnonce: &str) -> String {
+        let url = Url::parse("http://www.example.org/dir/index.html").unwrap();
+        let value = session
+            .response("Mufasa", password, &Method::GET, &url, cnonce)
+            .unwrap();
+        value.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn computes_rfc_2617_example() {
+        let session = session(
+            "Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", \
+             nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+             opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+        );
+        let value = response(&session, "Circle Of Life", "0a4f113b");
+        assert!(value.contains("response=\"6629fae49393a05397450978507c4ef1\""));
+        assert!(value.contains("qop=auth, nc=00000001, cnonce=\"0a4f113b\""));
+        assert!(value.contains("opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""));
+        assert!(value.contains("uri=\"/dir/index.html\""));
+    }
+
+    const RFC_7616_CHALLENGE: &str = "realm=\"http-auth@example.org\", qop=\"auth, auth-int\", \
+         nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+         opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+    const RFC_7616_CNONCE: &str = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+    #[test]
+    fn computes_rfc_7616_md5_example() {
+        let session = session(&format!("Digest algorithm=MD5, {}", RFC_7616_CHALLENGE));
+        let value = response(&session, "Circle of Life", RFC_7616_CNONCE);
+        assert!(value.contains("response=\"8ca523f5e9506fed4657c9700eebdbec\""));
+        assert!(value.contains("algorithm=MD5,"));
+    }
+
+    #[test]
+    fn computes_rfc_7616_sha256_example() {
+        let session = session(&format!("Digest algorithm=SHA-256, {}", RFC_7616_CHALLENGE));
+        let value = response(&session, "Circle of Life", RFC_7616_CNONCE);
+        assert!(value.contains(
+            "response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""
+        ));
+        assert!(value.contains("algorithm=SHA-256,"));
+    }
+
+    #[test]
+    fn rejects_unsupported_challenges() {
+        let params = |challenge: &str| parse_challenges(challenge).pop().unwrap().1;
+        assert!(DigestSession::new(&params("Digest realm=\"a\", nonce=\"b\"")).is_some());
+        assert!(DigestSession::new(&params("Digest realm=\"a\"")).is_none());
+        assert!(DigestSession::new(&params("Digest realm=a, nonce=b, qop=auth-int")).is_none());
+        let sha512 = params("Digest realm=a, nonce=b, algorithm=SHA-512");
+        assert!(DigestSession::new(&sha512).is_none());
+    }
+
+    #[test]
+    fn parses_multiple_challenges() {
+        let challenges = parse_challenges(
+            "Newauth realm=\"apps\", type=1, title=\"Login to \\\"apps\\\"\", \
+             Basic realm=\"simple\"",
+        );
+        assert_eq!(challenges.len(), 2);
+        assert_eq!(challenges[0].0, "newauth");
+        assert_eq!(challenges[0].1["title"], "Login to \"apps\"");
+        assert_eq!(challenges[0].1["type"], "1");
+        assert_eq!(challenges[1].0, "basic");
+        assert_eq!(challenges[1].1["realm"], "simple");
+    }
+
+    #[test]
+    fn reuses_nonce_with_counter() {
+        let mut auth = Authenticator::default();
+        let url = Url::parse("http://example.org/").unwrap();
+        auth.add(url.origin(), Credentials::digest("user", "pass"));
+        assert!(auth.authorization(&Method::GET, &url).is_none());
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static("Basic realm=a, Digest realm=\"a\", nonce=\"n\", qop=auth"),
+        );
+        assert!(auth.challenge(&url, &headers));
+        let first = auth.authorization(&Method::GET, &url).unwrap();
+        let second = auth.authorization(&Method::GET, &url).unwrap();
+        assert!(first.to_str().unwrap().contains("nc=00000001"));
+        assert!(second.to_str().unwrap().contains("nc=00000002"));
+    }
+
+    #[test]
+    fn uses_netrc_for_basic_challenges() {
+        let input = b"machine example.org login alice password secret\n";
+        let netrc = Netrc::parse(&input[..]).unwrap();
+        let mut auth = Authenticator::default();
+        auth.set_netrc(Some(netrc));
+        let url = Url::parse("http://example.org/").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        assert!(!auth.challenge(&url, &headers));
+        headers.insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Basic realm=a"));
+        assert!(auth.challenge(&url, &headers));
+        assert_eq!(
+            auth.authorization(&Method::GET, &url).unwrap(),
+            "Basic YWxpY2U6c2VjcmV0"
+        );
+        let other = Url::parse("http://example.com/").unwrap();
+        assert!(!auth.challenge(&other, &headers));
+    }
+}
@@ -1,11 +1,16 @@
 use std::cmp::min;
-use std::io::{self, BufReader, Read};
+use std::fmt;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use encoding_rs::{Encoding, UTF_8};
 use http::{header, StatusCode};
 use http::response::Parts;
 
+use decode::Decoder;
 use http1::read_chunked_body;
-use util::{get_content_length, is_chunked};
+use util::{get_charset, get_content_length, is_chunked};
 use Error;
 
 #[derive(Debug, PartialEq)]
@@ -16,96 +21,154 @@ enum BodyKind {
     CloseDelimited,
 }
 
-/// Contains a raw HTTP response body.
+/// Contains a HTTP response body.
 ///
 /// The body is readable and can be transformed to another more specific
 /// representation like a string or a custom type with the `FromBody` trait.
-pub struct Body<R> {
-    kind: BodyKind,
-    reader: BufReader<R>,
+///
+/// Content codings like gzip are removed while reading unless
+/// decompression is disabled on the client.
+pub struct Body<R: Read> {
+    inner: Decoder<RawBody<R>>,
+    none: bool,
+    text: TextOptions,
+}
+
+/// Controls how `FromBody for String` decodes text.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TextOptions {
+    pub(crate) fallback: &'static Encoding,
+    pub(crate) lossy: bool,
+}
+
+impl Default for TextOptions {
+    fn default() -> TextOptions {
+        TextOptions {
+            fallback: UTF_8,
+            lossy: false,
+        }
+    }
 }
 
-impl<R> Body<R> {
+impl<R: Read> Body<R> {
     /// Returns true if the message has no body.
     ///
     /// Responses to HEAD requests and responses with a 1xx, 204 and 304
     /// do not have a body. All other responses have a body but
     /// it may be empty.
     pub fn is_none(&self) -> bool {
-        self.kind == BodyKind::None
+        self.none
     }
 
+    /// Creates the body and removes the content codings from
+    /// the response header if the body will be decoded.
     pub(crate) fn from_response(
         reader: BufReader<R>,
-        response: &Parts,
+        response: &mut Parts,
         head: bool,
+        finished: Arc<AtomicBool>,
+        decompress: bool,
+        text: TextOptions,
     ) -> io::Result<Body<R>> {
-        // See http://httpwg.org/specs/rfc7230.html#rfc.section.3.3.3 for steps
-        // 1. no-body messages
-        let status = response.status;
-        if head || status.is_informational() || status == StatusCode::NO_CONTENT
-            || status == StatusCode::NOT_MODIFIED
-        {
-            return Ok(Body {
-                kind: BodyKind::None,
-                reader,
-            });
+        let raw = RawBody {
+            kind: body_kind(response, head)?,
+            reader,
+            finished,
+        };
+        raw.check_finished();
+        let none = raw.kind == BodyKind::None;
+        let inner = if decompress && !none {
+            Decoder::from_response(raw, response)
+        } else {
+            Decoder::Identity(raw)
+        };
+        Ok(Body { inner, none, text })
+    }
+}
+
+impl<R: Read> Read for Body<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_len = self.inner.read(buf)?;
+        if read_len == 0 && !buf.is_empty() {
+            // A decoder may stop before the end of the message,
+            // read the rest so the connection can be reused.
+            io::copy(self.inner.get_mut(), &mut io::sink())?;
         }
-        // 2. CONNECT messages (not implemented)
-        // 3. Chunked message
-        if response.headers.contains_key(&header::TRANSFER_ENCODING) {
-            if is_chunked(response.headers.get_all(&header::TRANSFER_ENCODING)) {
-                return Ok(Body {
-                    kind: BodyKind::Chunked(0, false),
-                    reader,
-                });
-            } else {
-                return Ok(Body {
-                    kind: BodyKind::CloseDelimited,
-                    reader,
-                });
-            }
+        Ok(read_len)
+    }
+}
+
+/// The message body without content codings removed.
+struct RawBody<R> {
+    kind: BodyKind,
+    reader: BufReader<R>,
+    finished: Arc<AtomicBool>,
+}
+
+impl<R> RawBody<R> {
+    fn check_finished(&self) {
+        let finished = matches!(
+            self.kind,
+            BodyKind::None | BodyKind::Fixed(0) | BodyKind::Chunked(0, true)
+        );
+        if finished {
+            self.finished.store(true, Ordering::SeqCst);
         }
-        // 4. + 5. Fixed body messages
-        if response.headers.contains_key(&header::CONTENT_LENGTH) {
-            if let Some(len) = get_content_length(response.headers.get_all(&header::CONTENT_LENGTH))
-            {
-                return Ok(Body {
-                    kind: BodyKind::Fixed(len),
-                    reader,
-                });
-            } else {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    Error::BadResponse,
-                ));
-            }
+    }
+}
+
+fn body_kind(response: &Parts, head: bool) -> io::Result<BodyKind> {
+    // See http://httpwg.org/specs/rfc7230.html#rfc.section.3.3.3 for steps
+    // 1. no-body messages
+    let status = response.status;
+    if head || status.is_informational() || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+    {
+        return Ok(BodyKind::None);
+    }
+    // 2. CONNECT messages (not implemented)
+    // 3. Chunked message
+    if response.headers.contains_key(&header::TRANSFER_ENCODING) {
+        if is_chunked(response.headers.get_all(&header::TRANSFER_ENCODING)) {
+            return Ok(BodyKind::Chunked(0, false));
+        } else {
+            return Ok(BodyKind::CloseDelimited);
+        }
+    }
+    // 4. + 5. Fixed body messages
+    if response.headers.contains_key(&header::CONTENT_LENGTH) {
+        if let Some(len) = get_content_length(response.headers.get_all(&header::CONTENT_LENGTH)) {
+            return Ok(BodyKind::Fixed(len));
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                Error::BadResponse,
+            ));
         }
-        // (6. request only)
-        // 7. read until connection is closed
-        return Ok(Body {
-            kind: BodyKind::CloseDelimited,
-            reader,
-        });
     }
+    // (6. request only)
+    // 7. read until connection is closed
+    Ok(BodyKind::CloseDelimited)
 }
 
-impl<R: Read> Read for Body<R> {
+impl<R: Read> Read for RawBody<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match self.kind {
-            BodyKind::None => Ok(0),
-            BodyKind::Fixed(0) => Ok(0),
+        let read_len = match self.kind {
+            BodyKind::None => 0,
+            BodyKind::Fixed(0) => 0,
             BodyKind::Fixed(ref mut len) => {
                 let buf_len = buf.len();
                 let read_len = self.reader.read(&mut buf[..min(buf_len, *len)])?;
                 *len -= read_len;
-                Ok(read_len)
+                read_len
             }
             BodyKind::Chunked(ref mut chunk_len, ref mut last) => {
-                read_chunked_body(&mut self.reader, buf, chunk_len, last)
+                read_chunked_body(&mut self.reader, buf, chunk_len, last)?
             }
-            BodyKind::CloseDelimited => self.reader.read(buf),
-        }
+            BodyKind::CloseDelimited => self.reader.read(buf)?,
+        };
+        self.check_finished();
+        Ok(read_len)
     }
 }
 
@@ -134,53 +197,255 @@ impl FromBody for () {
 
 impl FromBody for Vec<u8> {
     fn from_body<R: Read>(_response: &Parts, body: &mut Body<R>) -> io::Result<Self> {
-        // TODO: Handle compression encoding.
         let mut data = Vec::new();
         body.read_to_end(&mut data)?;
         Ok(data)
     }
 }
 
+/// Decodes the body as text.
+///
+/// A byte order mark takes precedence over the `charset` parameter of
+/// the `Content-Type` header. If neither is present the fallback
+/// encoding of the client is used, by default UTF-8.
+/// Malformed byte sequences are an error unless lossy decoding was
+/// enabled on the client, then they are replaced with U+FFFD.
 impl FromBody for String {
-    fn from_body<R: Read>(_response: &Parts, body: &mut Body<R>) -> io::Result<Self> {
-        // TODO: Handle text encodings other than UTF-8
-        let mut data = String::new();
-        body.read_to_string(&mut data)?;
-        Ok(data)
+    fn from_body<R: Read>(response: &Parts, body: &mut Body<R>) -> io::Result<Self> {
+        let mut data = Vec::new();
+        body.read_to_end(&mut data)?;
+        let encoding = get_charset(&response.headers).unwrap_or(body.text.fallback);
+        let (text, encoding, malformed) = encoding.decode(&data);
+        if malformed && !body.text.lossy {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("response body is not valid {}", encoding.name()),
+            ));
+        }
+        Ok(text.into_owned())
     }
 }
 
+/// Write request bodies from strong types.
+///
+/// Used to send a request body to the server. If the length of the body
+/// is known in advance it is sent in the `Content-Length` header,
+/// otherwise the body is sent with chunked transfer encoding.
 pub trait ToBody {
-    fn to_body(&self) -> &[u8];
-    fn len(&self) -> usize;
+    /// Returns the length of the body in bytes if it is known.
+    fn content_length(&self) -> Option<u64>;
+
+    /// Writes the complete body to the connection.
+    fn write_body<W: Write>(&mut self, writer: &mut W) -> io::Result<()>;
+
+    /// Prepares the body to be written again.
+    ///
+    /// Called before a request is sent again, for example after a
+    /// redirect with status 307 or 308. Returns `false` if the body
+    /// cannot be sent again. The default implementation returns `false`.
+    fn rewind(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Returns the complete body if it is held in memory.
+    ///
+    /// Used to compute a hash of the body when signing requests.
+    /// The default implementation returns `None`.
+    fn bytes(&self) -> Option<&[u8]> {
+        None
+    }
 }
 
 impl ToBody for () {
-    fn to_body(&self) -> &[u8] {
-        &[]
+    fn content_length(&self) -> Option<u64> {
+        Some(0)
     }
 
-    fn len(&self) -> usize {
-        0
+    fn write_body<W: Write>(&mut self, _writer: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        Some(&[])
     }
 }
 
 impl ToBody for Vec<u8> {
-    fn to_body(&self) -> &[u8] {
-        &self[..]
+    fn content_length(&self) -> Option<u64> {
+        Some(self.len() as u64)
     }
 
-    fn len(&self) -> usize {
-        self.len()
+    fn write_body<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self[..])
+    }
+
+    fn rewind(&mut self) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        Some(self)
     }
 }
 
 impl ToBody for String {
-    fn to_body(&self) -> &[u8] {
-        self.as_bytes()
+    fn content_length(&self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
+
+    fn write_body<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(self.as_bytes())
+    }
+
+    fn rewind(&mut self) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        Some(self.as_bytes())
     }
+}
 
-    fn len(&self) -> usize {
-        self.len()
+/// A request body that is read from a reader while it is sent.
+///
+/// Use it to upload files, pipes or generated data without
+/// loading them into memory first.
+///
+/// A stream can only be sent once unless it is made rewindable with
+/// `rewindable`. Redirects with status 307 or 308 fail with
+/// `Error::UnrewindableBody` for other streams.
+pub struct StreamBody<R> {
+    reader: R,
+    len: Option<u64>,
+    rewind: Option<(u64, SeekFn<R>)>,
+}
+
+impl<R: Read> StreamBody<R> {
+    /// Creates a body of unknown length.
+    ///
+    /// The body is sent with chunked transfer encoding.
+    pub fn new(reader: R) -> StreamBody<R> {
+        StreamBody {
+            reader,
+            len: None,
+            rewind: None,
+        }
+    }
+
+    /// Creates a body with a known length in bytes.
+    ///
+    /// Sending the request fails if the reader ends early.
+    /// Data after `len` bytes is not sent.
+    pub fn with_length(reader: R, len: u64) -> StreamBody<R> {
+        StreamBody {
+            reader,
+            len: Some(len),
+            rewind: None,
+        }
+    }
+
+    /// Returns the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read + Seek> StreamBody<R> {
+    /// Allows the body to be sent again by seeking back.
+    ///
+    /// The current position of the reader is remembered
+    /// and the reader is moved back there when the body
+    /// is sent again, for example for files.
+    pub fn rewindable(mut self) -> io::Result<StreamBody<R>> {
+        let start = self.reader.stream_position()?;
+        let seek: SeekFn<R> = seek_to::<R>;
+        self.rewind = Some((start, seek));
+        Ok(self)
+    }
+}
+
+/// Moves a reader back to the start of a rewindable body.
+type SeekFn<R> = fn(&mut R, u64) -> io::Result<()>;
+
+fn seek_to<R: Seek>(reader: &mut R, pos: u64) -> io::Result<()> {
+    reader.seek(SeekFrom::Start(pos)).map(|_| ())
+}
+
+impl<R: Read> ToBody for StreamBody<R> {
+    fn content_length(&self) -> Option<u64> {
+        self.len
+    }
+
+    fn write_body<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if let Some(len) = self.len {
+            let written = io::copy(&mut self.reader.by_ref().take(len), writer)?;
+            if written != len {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "request body is shorter than its length",
+                ));
+            }
+        } else {
+            io::copy(&mut self.reader, writer)?;
+        }
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> io::Result<bool> {
+        if let Some((start, seek)) = self.rewind {
+            seek(&mut self.reader, start)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+impl<R> fmt::Debug for StreamBody<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StreamBody")
+            .field("len", &self.len)
+            .field("rewindable", &self.rewind.is_some())
+            .finish()
+    }
+}
+
+/// A request body that is dropped when a redirect changes the method to GET.
+pub(crate) enum RedirectBody<A> {
+    Original(A),
+    Empty,
+}
+
+impl<A: ToBody> ToBody for RedirectBody<A> {
+    fn content_length(&self) -> Option<u64> {
+        match *self {
+            RedirectBody::Original(ref body) => body.content_length(),
+            RedirectBody::Empty => Some(0),
+        }
+    }
+
+    fn write_body<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        match *self {
+            RedirectBody::Original(ref mut body) => body.write_body(writer),
+            RedirectBody::Empty => Ok(()),
+        }
+    }
+
+    fn rewind(&mut self) -> io::Result<bool> {
+        match *self {
+            RedirectBody::Original(ref mut body) => body.rewind(),
+            RedirectBody::Empty => Ok(true),
+        }
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        match *self {
+            RedirectBody::Original(ref body) => body.bytes(),
+            RedirectBody::Empty => Some(&[]),
+        }
     }
 }
@@ -1,23 +1,407 @@
-use std::collections::HashMap;
-use std::io::{self, BufReader, BufWriter, Read, Write};
-use std::net::TcpStream;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-use http::{header, Method, Request, Response};
-use http::header::HeaderValue;
-use http::url::Origin;
-use native_tls::{HandshakeError, TlsConnector, TlsStream};
+use base64;
+use encoding_rs::Encoding;
+use http::{header, Method, Request, Response, StatusCode, Url};
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::url::Host;
 
-use body::{Body, FromBody, ToBody};
-use http1;
-use util::{is_persistent_connection, is_redirect_method_get, is_redirect_status, wrap_error};
+use auth::{Authenticator, Credentials};
+use aws::AwsSigner;
+use body::{Body, FromBody, RedirectBody, TextOptions, ToBody};
+use cookie::CookieJar;
+use decode::accept_encoding;
+use http1::{self, ChunkedWriter};
+use netrc::Netrc;
+use pool::{IdleConnection, Pool, PoolConfig, PoolKey, PooledConnection, Stream};
+use proxy::Proxy;
+use redirect::{Attempt, RedirectHistory, RedirectPolicy};
+use tls::{spki_hash, Certificate, Identity, TlsBackend, TlsConfig, TlsConnector, TlsInfo,
+          TlsStream, TlsVersion};
+use util::{is_closed_connection, is_idempotent, is_persistent_connection, is_redirect_method_get,
+           is_redirect_status, map_timeout, remaining_timeout, remove_body_headers, wrap_error};
 
 /// A HTTP(S) client.
 ///
 /// Use `Client::new().fetch(request)` to make a single request.
+/// Use `Client::builder()` to configure the client before use.
+///
+/// Clones of a client share the connection pool, cookies, credentials and
+/// configuration. A client can be used from many threads at once.
+#[derive(Clone)]
 pub struct Client {
-    tcp_streams: HashMap<Origin, TcpStream>,
-    tls_streams: HashMap<Origin, TlsStream<TcpStream>>,
-    tls_connector: Option<TlsConnector>,
+    inner: Arc<ClientRef>,
+}
+
+struct ClientRef {
+    pool: Arc<Mutex<Pool>>,
+    tls_connector: Mutex<Option<Arc<dyn TlsConnector>>>,
+    cookie_jar: Option<Mutex<CookieJar>>,
+    auth: Mutex<Authenticator>,
+    config: Config,
+}
+
+#[derive(Clone, Debug)]
+struct Config {
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    decompress: bool,
+    text: TextOptions,
+    redirect_policy: Arc<RedirectPolicy>,
+    sensitive_headers: Vec<HeaderName>,
+    proxies: Vec<Proxy>,
+    aws_signer: Option<AwsSigner>,
+    tls: TlsConfig,
+    pool: PoolConfig,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            connect_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            timeout: None,
+            decompress: true,
+            text: TextOptions::default(),
+            redirect_policy: Arc::new(RedirectPolicy::default()),
+            sensitive_headers: vec![
+                header::AUTHORIZATION,
+                header::COOKIE,
+                header::PROXY_AUTHORIZATION,
+            ],
+            proxies: Vec::new(),
+            aws_signer: None,
+            tls: TlsConfig::default(),
+            pool: PoolConfig::default(),
+        }
+    }
+}
+
+/// Configures and creates a `Client`.
+///
+/// All timeouts are disabled by default.
+#[derive(Debug, Default)]
+pub struct ClientBuilder {
+    config: Config,
+    cookie_jar: Option<CookieJar>,
+    auth: Authenticator,
+    env_proxy: bool,
+}
+
+impl ClientBuilder {
+    /// Creates a new builder with the default configuration.
+    pub fn new() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    /// Sets the maximum time to wait for a TCP connection to be established.
+    ///
+    /// If a host resolves to multiple addresses the timeout applies
+    /// to each address separately.
+    pub fn connect_timeout(&mut self, timeout: Duration) -> &mut ClientBuilder {
+        self.config.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the maximum time a single read from a connection may block.
+    pub fn read_timeout(&mut self, timeout: Duration) -> &mut ClientBuilder {
+        self.config.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the maximum time a single write to a connection may block.
+    pub fn write_timeout(&mut self, timeout: Duration) -> &mut ClientBuilder {
+        self.config.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the maximum time a whole request may take.
+    ///
+    /// The deadline covers connecting, sending the request, following all
+    /// redirects and reading the response including the body, also when
+    /// the body is read from the `Body` returned by `Client::send`.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut ClientBuilder {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the maximum number of idle connections kept for each origin.
+    ///
+    /// Set to zero to disable reuse of connections. The default is 8.
+    pub fn pool_max_idle_per_host(&mut self, max: usize) -> &mut ClientBuilder {
+        self.config.pool.max_idle_per_host = max;
+        self
+    }
+
+    /// Sets the maximum number of idle connections kept in total.
+    ///
+    /// The least recently used connections are closed first.
+    /// The default is 64.
+    pub fn pool_max_idle(&mut self, max: usize) -> &mut ClientBuilder {
+        self.config.pool.max_idle = max;
+        self
+    }
+
+    /// Sets how long a connection may be idle before it is closed.
+    ///
+    /// `None` keeps idle connections until the limits are reached.
+    /// The default is 90 seconds.
+    pub fn pool_idle_timeout(&mut self, timeout: Option<Duration>) -> &mut ClientBuilder {
+        self.config.pool.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets how long after opening a connection it is no longer reused.
+    ///
+    /// Requests already sent on the connection are not interrupted.
+    /// Unlimited by default.
+    pub fn pool_max_lifetime(&mut self, lifetime: Option<Duration>) -> &mut ClientBuilder {
+        self.config.pool.max_lifetime = lifetime;
+        self
+    }
+
+    /// Sets the policy that decides which redirects are followed.
+    ///
+    /// The default policy follows up to 20 redirects.
+    pub fn redirect(&mut self, policy: RedirectPolicy) -> &mut ClientBuilder {
+        self.config.redirect_policy = Arc::new(policy);
+        self
+    }
+
+    /// Sets the headers removed from requests redirected to another origin.
+    ///
+    /// Credentials for one server must not be sent to another server
+    /// just because it was the target of a redirect. The default list is
+    /// `Authorization`, `Cookie` and `Proxy-Authorization`, the given
+    /// headers replace the list.
+    pub fn sensitive_headers<I>(&mut self, headers: I) -> &mut ClientBuilder
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        self.config.sensitive_headers = headers.into_iter().collect();
+        self
+    }
+
+    /// Adds a proxy server.
+    ///
+    /// Proxies are tried in the order they were added,
+    /// the first proxy for the scheme of the request URL is used.
+    pub fn proxy(&mut self, proxy: Proxy) -> &mut ClientBuilder {
+        self.config.proxies.push(proxy);
+        self
+    }
+
+    /// Enables or disables proxies from environment variables.
+    ///
+    /// If enabled the client reads `http_proxy`, `https_proxy`, `all_proxy`
+    /// and `no_proxy` when it is built. Uppercase names are read as well
+    /// except `HTTP_PROXY`, which CGI servers set from a request header.
+    /// These proxies are used after proxies added with `proxy`.
+    /// Disabled by default.
+    pub fn proxy_from_env(&mut self, enable: bool) -> &mut ClientBuilder {
+        self.env_proxy = enable;
+        self
+    }
+
+    /// Enables or disables the cookie store.
+    ///
+    /// If enabled the client stores cookies set by servers and sends
+    /// them with later requests and redirects. Disabled by default.
+    pub fn cookie_store(&mut self, enable: bool) -> &mut ClientBuilder {
+        if !enable {
+            self.cookie_jar = None;
+        } else if self.cookie_jar.is_none() {
+            self.cookie_jar = Some(CookieJar::new());
+        }
+        self
+    }
+
+    /// Enables the cookie store with the cookies in `jar`.
+    pub fn cookie_jar(&mut self, jar: CookieJar) -> &mut ClientBuilder {
+        self.cookie_jar = Some(jar);
+        self
+    }
+
+    /// Authenticates requests to the origin of `url` with `credentials`.
+    ///
+    /// Credentials are only used for URLs with the same scheme, host
+    /// and port. They replace earlier credentials for the origin.
+    /// An `Authorization` header set on a request takes precedence.
+    pub fn credentials(&mut self, url: &Url, credentials: Credentials) -> &mut ClientBuilder {
+        self.auth.add(url.origin(), credentials);
+        self
+    }
+
+    /// Trusts `certificate` as root certificate in addition to the system roots.
+    pub fn add_root_certificate(&mut self, certificate: Certificate) -> &mut ClientBuilder {
+        self.config.tls.roots.push(certificate);
+        self
+    }
+
+    /// Authenticates the client with a certificate in TLS handshakes.
+    pub fn identity(&mut self, identity: Identity) -> &mut ClientBuilder {
+        self.config.tls.identity = Some(identity);
+        self
+    }
+
+    /// Sets the minimum TLS version accepted.
+    pub fn min_tls_version(&mut self, version: TlsVersion) -> &mut ClientBuilder {
+        self.config.tls.min_version = Some(version);
+        self
+    }
+
+    /// Selects the TLS implementation.
+    pub fn tls_backend(&mut self, backend: TlsBackend) -> &mut ClientBuilder {
+        self.config.tls.backend = backend;
+        self
+    }
+
+    /// Sets the protocols offered with ALPN in TLS handshakes.
+    ///
+    /// Only HTTP/1.x is spoken by the client. Empty by default.
+    pub fn alpn_protocols(&mut self, protocols: &[&str]) -> &mut ClientBuilder {
+        self.config.tls.alpn_protocols = protocols.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// Uses `name` instead of `host` to verify the server certificate.
+    ///
+    /// The client still connects to `host` but sends `name` with SNI and
+    /// expects a certificate for `name`. Both may be domains or IP addresses,
+    /// IPv6 addresses are written without brackets.
+    pub fn tls_server_name(&mut self, host: &str, name: &str) -> &mut ClientBuilder {
+        let host = host.trim_matches(|c| c == '[' || c == ']').to_ascii_lowercase();
+        self.config.tls.server_names.retain(|(h, _)| *h != host);
+        self.config.tls.server_names.push((host, name.to_string()));
+        self
+    }
+
+    /// Accepts only servers presenting one of the public keys `pins` for `host`.
+    ///
+    /// Each pin is the base64 encoded SHA-256 hash of a SubjectPublicKeyInfo
+    /// as in HTTP Public Key Pinning, optionally prefixed with `sha256/`.
+    /// A connection is accepted if any certificate of the chain matches.
+    /// Pins are checked after the certificate is verified, other hosts are
+    /// not pinned.
+    ///
+    /// `TlsBackend::NativeTls` cannot access the certificate chain and only
+    /// checks the leaf certificate, pins of intermediate or root certificates
+    /// never match. A warning is logged when such a client connects.
+    ///
+    /// Fails with `InvalidInput` if a pin is not a base64 encoded SHA-256 hash.
+    pub fn pin_public_keys(
+        &mut self,
+        host: &str,
+        pins: &[&str],
+    ) -> io::Result<&mut ClientBuilder> {
+        let host = host.trim_matches(|c| c == '[' || c == ']').to_ascii_lowercase();
+        let pins = pins.iter()
+            .map(|pin| match base64::decode(pin.trim_start_matches("sha256/")) {
+                Ok(ref hash) if hash.len() == 32 => Ok(base64::encode(hash)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("pin {:?} is not a base64 encoded SHA-256 hash", pin),
+                )),
+            })
+            .collect::<io::Result<_>>()?;
+        self.config.tls.pins.retain(|(h, _)| *h != host);
+        self.config.tls.pins.push((host, pins));
+        Ok(self)
+    }
+
+    /// Disables verification of server certificates and host names.
+    ///
+    /// # Warning
+    ///
+    /// Any server can impersonate any other server and read or change all
+    /// transferred data. Only use this to test against servers with
+    /// self-signed certificates. Disabled by default.
+    pub fn danger_accept_invalid_certs(&mut self, accept: bool) -> &mut ClientBuilder {
+        self.config.tls.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Signs all requests with AWS Signature Version 4.
+    ///
+    /// Requests with an `Authorization` header are not signed.
+    pub fn aws_signer(&mut self, signer: AwsSigner) -> &mut ClientBuilder {
+        self.config.aws_signer = Some(signer);
+        self
+    }
+
+    /// Uses credentials from a netrc file.
+    ///
+    /// If a server without configured credentials responds with
+    /// `401 Unauthorized` and requests basic authentication, the request is
+    /// sent again with the netrc credentials for the host. They are then used
+    /// for all later requests to the origin. Pass `None` to disable.
+    pub fn netrc(&mut self, netrc: Option<Netrc>) -> &mut ClientBuilder {
+        self.auth.set_netrc(netrc);
+        self
+    }
+
+    /// Enables or disables decoding of compressed response bodies.
+    ///
+    /// If enabled the client sends an `Accept-Encoding` header unless
+    /// the request already has one and removes the content codings
+    /// gzip, deflate and br from response bodies as far as they are
+    /// enabled as cargo features. Decoded responses no longer contain
+    /// the `Content-Encoding` and `Content-Length` headers.
+    ///
+    /// Enabled by default.
+    pub fn decompress(&mut self, enable: bool) -> &mut ClientBuilder {
+        self.config.decompress = enable;
+        self
+    }
+
+    /// Sets the encoding used to decode text without a declared charset.
+    ///
+    /// The label is one of the encoding labels defined by the
+    /// [WHATWG Encoding Standard](https://encoding.spec.whatwg.org/#names-and-labels),
+    /// for example `"windows-1252"` or `"shift_jis"`. The default is UTF-8.
+    /// Fails with `InvalidInput` if the label is unknown.
+    pub fn fallback_charset(&mut self, label: &str) -> io::Result<&mut ClientBuilder> {
+        self.config.text.fallback = Encoding::for_label(label.as_bytes()).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown charset label {:?}", label),
+            )
+        })?;
+        Ok(self)
+    }
+
+    /// Replaces malformed byte sequences in text instead of failing.
+    ///
+    /// If enabled invalid sequences in a response body converted to a
+    /// `String` are replaced with U+FFFD REPLACEMENT CHARACTER.
+    /// Disabled by default.
+    pub fn lossy_text(&mut self, enable: bool) -> &mut ClientBuilder {
+        self.config.text.lossy = enable;
+        self
+    }
+
+    /// Creates a client with the configuration of this builder.
+    pub fn build(&self) -> Client {
+        let mut config = self.config.clone();
+        if self.env_proxy {
+            config.proxies.extend(Proxy::from_env());
+        }
+        Client {
+            inner: Arc::new(ClientRef {
+                pool: Arc::new(Mutex::new(Pool::new(config.pool))),
+                tls_connector: Mutex::new(None),
+                cookie_jar: self.cookie_jar.clone().map(Mutex::new),
+                auth: Mutex::new(self.auth.clone()),
+                config,
+            }),
+        }
+    }
 }
 
 impl Client {
@@ -26,20 +410,65 @@ impl Client {
     /// Try to use a client for multiple connections as the client may
     /// be able to reuse existing connections.
     pub fn new() -> Client {
-        Client {
-            tcp_streams: HashMap::new(),
-            tls_streams: HashMap::new(),
-            tls_connector: None,
-        }
+        ClientBuilder::new().build()
     }
 
-    fn get_tls_connector(&mut self) -> io::Result<&TlsConnector> {
-        if let Some(ref connector) = self.tls_connector {
-            return Ok(connector);
-        } else {
-            self.tls_connector = Some(wrap_error(wrap_error(TlsConnector::builder())?.build())?);
-            return self.get_tls_connector();
+    /// Creates a builder to configure a new client.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Returns a copy of the cookie store if it is enabled.
+    ///
+    /// The store is shared by all clones of the client,
+    /// changes to the copy do not affect the client.
+    pub fn cookie_jar(&self) -> Option<CookieJar> {
+        self.with_cookie_jar(|jar| jar.clone())
+    }
+
+    /// Calls `f` with the cookie store if it is enabled.
+    ///
+    /// Requests of all clones of the client wait for `f` to return
+    /// before they use cookies, do not send requests from `f`.
+    pub fn with_cookie_jar<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut CookieJar) -> R,
+    {
+        self.inner
+            .cookie_jar
+            .as_ref()
+            .map(|jar| f(&mut jar.lock().expect("cookie jar is not poisoned")))
+    }
+
+    /// Returns the idle connections kept for later requests.
+    pub fn idle_connections(&self) -> Vec<IdleConnection> {
+        self.inner.pool
+            .lock()
+            .expect("connection pool is not poisoned")
+            .idle_connections()
+    }
+
+    /// Closes all idle connections.
+    ///
+    /// Connections in use are still returned to the pool afterwards.
+    pub fn clear_pool(&self) {
+        self.inner.pool
+            .lock()
+            .expect("connection pool is not poisoned")
+            .clear();
+    }
+
+    fn get_tls_connector(&self) -> io::Result<Arc<dyn TlsConnector>> {
+        let mut tls_connector = self.inner
+            .tls_connector
+            .lock()
+            .expect("TLS connector is not poisoned");
+        if let Some(ref connector) = *tls_connector {
+            return Ok(connector.clone());
         }
+        let connector = self.inner.config.tls.connector()?;
+        *tls_connector = Some(connector.clone());
+        Ok(connector)
     }
 
     /// Send a HTTP request.
@@ -47,157 +476,387 @@ impl Client {
     /// This is the main function of the crate.
     /// It will send the request using either HTTP or HTTPS to the server.
     /// If possible it will reuse connections from the same client.
-    /// The client follows up to 20 redirects.
+    /// Requests with the methods GET, HEAD, PUT, DELETE and OPTIONS are sent
+    /// again on a new connection if the server closed a reused connection
+    /// before responding.
+    /// By default the client follows up to 20 redirects,
+    /// see `ClientBuilder::redirect` to change this.
+    /// The response extensions contain a `RedirectHistory` with
+    /// the redirects followed and the effective URL and for HTTPS
+    /// a `TlsInfo` with details of the TLS session.
     /// The body is automatically converted to the expected format.
-    pub fn fetch<A: ToBody, B: FromBody>(&mut self, request: Request<A>) -> io::Result<Response<B>> {
+    ///
+    /// If a configured timeout expires the error has the kind `TimedOut`
+    /// and contains `Error::TimedOut`.
+    pub fn fetch<A: ToBody, B: FromBody>(&self, request: Request<A>) -> io::Result<Response<B>> {
+        let (parts, mut body) = self.send(request)?.into_parts();
+        match FromBody::from_body(&parts, &mut body) {
+            Ok(typed_body) => Ok(Response::from_parts(parts, typed_body)),
+            Err(err) => {
+                warn!("Encountered error: {:?}", err);
+                Err(err)
+            }
+        }
+    }
+
+    /// Send a HTTP request and stream the response body.
+    ///
+    /// Works like `fetch` but returns as soon as the response header
+    /// was received. The body is read incrementally from the returned
+    /// `Body`. The connection is returned to the client for reuse once
+    /// the body was read completely, if the body is dropped early
+    /// the connection is closed.
+    ///
+    /// The read timeout and the deadline set with `ClientBuilder::timeout`
+    /// still apply to reads from the body.
+    pub fn send<A: ToBody>(
+        &self,
+        request: Request<A>,
+    ) -> io::Result<Response<Body<PooledConnection>>> {
         info!("Fetching {} {}", request.method(), request.url());
-        match self.fetch_redirect(request, 0) {
+        let deadline = self.inner.config.timeout.map(|timeout| Instant::now() + timeout);
+        match self.fetch_redirect(request, deadline) {
             Ok(response) => Ok(response),
             Err(err) => {
+                let err = map_timeout(err);
                 warn!("Encountered error: {:?}", err);
                 Err(err)
             }
         }
     }
 
-    fn fetch_redirect<A: ToBody, B: FromBody>(
-        &mut self,
-        mut request: Request<A>,
-        counter: u8,
-    ) -> io::Result<Response<B>> {
-        if counter >= 20 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                Error::TooManyRedirects,
-            ));
-        }
-        let response = self.fetch_network(&mut request)?;
-        if is_redirect_status(response.status()) {
-            if let Some(location) = response.headers().get(header::LOCATION) {
-                let location_url = wrap_error(request.url().join(wrap_error(location.to_str())?))?;
-                *request.url_mut() = location_url;
+    fn fetch_redirect<A: ToBody>(
+        &self,
+        request: Request<A>,
+        deadline: Option<Instant>,
+    ) -> io::Result<Response<Body<PooledConnection>>> {
+        let (head, body) = request.into_parts();
+        let mut request = Request::from_parts(head, RedirectBody::Original(body));
+        let mut history = Vec::new();
+        let mut statuses = Vec::new();
+        loop {
+            history.push(request.url().clone());
+            let mut response = self.fetch_network(&mut request, deadline)?;
+            if response.status() == StatusCode::UNAUTHORIZED
+                && self.inner
+                    .auth
+                    .lock()
+                    .expect("authenticator is not poisoned")
+                    .challenge(request.url(), response.headers())
+                && request.body_mut().rewind()?
+            {
+                info!("Sending request to {} again with credentials", request.url());
+                io::copy(response.body_mut(), &mut io::sink())?;
+                drop(response);
+                response = self.fetch_network(&mut request, deadline)?;
+            }
+            let status = response.status();
+            if !is_redirect_status(status) {
+                response
+                    .extensions_mut()
+                    .insert(RedirectHistory::new(history, statuses));
+                return Ok(response);
+            }
+            let location_url = if let Some(location) = response.headers().get(header::LOCATION) {
+                wrap_error(request.url().join(wrap_error(location.to_str())?))?
             } else {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidData,
                     Error::BadResponse,
                 ));
+            };
+            if !self.inner.config
+                .redirect_policy
+                .check(Attempt::new(status, &location_url, &history))?
+            {
+                info!("Not following '{}' redirect to {}", status, location_url);
+                response
+                    .extensions_mut()
+                    .insert(RedirectHistory::new(history, statuses));
+                return Ok(response);
+            }
+            statuses.push(status);
+            info!("Following '{}' redirect to {}", status, location_url);
+            if request.url().origin() != location_url.origin() {
+                for name in &self.inner.config.sensitive_headers {
+                    if request.headers_mut().remove(name).is_some() {
+                        debug!("Removed {} header in cross-origin redirect", name.as_str());
+                    }
+                }
             }
-            info!(
-                "Following '{}' redirect to {}",
-                response.status(),
-                request.url()
-            );
-            if is_redirect_method_get(response.status(), request.method()) {
+            *request.url_mut() = location_url;
+            // Discard the body so the connection can be reused.
+            io::copy(response.body_mut(), &mut io::sink())?;
+            drop(response);
+            if is_redirect_method_get(status, request.method()) {
                 info!(
                     "Method changed in redirect from {} to GET",
                     request.method()
                 );
-                let head = request.into_parts().0;
-                let mut request = Request::from_parts(head, ());
                 *request.method_mut() = Method::GET;
-                return self.fetch_redirect(request, counter + 1);
+                *request.body_mut() = RedirectBody::Empty;
+                remove_body_headers(request.headers_mut());
+            } else if !request.body_mut().rewind()? {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    Error::UnrewindableBody,
+                ));
+            }
+        }
+    }
+
+    fn fetch_network<A: ToBody>(
+        &self,
+        request: &mut Request<A>,
+        deadline: Option<Instant>,
+    ) -> io::Result<Response<Body<PooledConnection>>> {
+        let proxy = self.inner.config
+            .proxies
+            .iter()
+            .find(|proxy| proxy.intercepts(request.url()))
+            .cloned();
+        let key = PoolKey::new(
+            request.url().origin(),
+            proxy.as_ref().map(|proxy| proxy.url().clone()),
+        );
+        let mut reuse = true;
+        let (mut response, tls_info) = loop {
+            let pooled = if reuse {
+                self.inner.pool
+                    .lock()
+                    .expect("connection pool is not poisoned")
+                    .take(&key)
             } else {
-                return self.fetch_redirect(request, counter + 1);
+                None
             };
-        } else {
-            Ok(response)
+            let reused = pooled.is_some();
+            let (stream, created) = if let Some(pooled) = pooled {
+                debug!("Reusing connection to {:?}", key);
+                pooled
+            } else if request.url().scheme() == "http" {
+                let stream = self.connect_proxied(request.url(), proxy.as_ref(), deadline)?;
+                (Stream::Tcp(stream), Instant::now())
+            } else if request.url().scheme() == "https" {
+                let stream = self.connect_proxied(request.url(), proxy.as_ref(), deadline)?;
+                let stream = self.connect_tls(request.url(), stream, deadline)?;
+                (Stream::Tls(stream), Instant::now())
+            } else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    Error::WrongScheme,
+                ));
+            };
+            let tls_info = match stream {
+                Stream::Tls(ref stream) => Some(TlsInfo::new(&**stream)),
+                Stream::Tcp(_) => None,
+            };
+            let mut connection =
+                PooledConnection::new(stream, key.clone(), created, &self.inner.pool);
+            let config = &self.inner.config;
+            connection.set_timeouts(config.read_timeout, config.write_timeout, deadline)?;
+            let http_proxy = proxy.as_ref().filter(|proxy| proxy.forwards(request.url()));
+            match self.fetch_data(request, connection, http_proxy) {
+                Ok(response) => break (response, tls_info),
+                // The server may close an idle connection while the request
+                // is sent, before any response was received.
+                Err(err) => {
+                    if reused && is_closed_connection(&err) && is_idempotent(request.method())
+                        && request.body_mut().rewind()?
+                    {
+                        info!("Reused connection to {:?} failed, retrying: {}", key, err);
+                        reuse = false;
+                        continue;
+                    }
+                    return Err(err);
+                }
+            }
+        };
+        if let Some(tls_info) = tls_info {
+            response.extensions_mut().insert(tls_info);
         }
+        if let Some(ref cookie_jar) = self.inner.cookie_jar {
+            let mut cookie_jar = cookie_jar.lock().expect("cookie jar is not poisoned");
+            cookie_jar.store_response(request.url(), response.headers());
+        }
+        Ok(response)
     }
 
-    fn fetch_network<A: ToBody, B: FromBody>(
-        &mut self,
-        request: &mut Request<A>,
-    ) -> io::Result<Response<B>> {
-        if request.url().scheme() == "http" {
-            self.fetch_network_http(request)
-        } else if request.url().scheme() == "https" {
-            self.fetch_network_https(request)
+    /// Connects to the host of `url`, through `proxy` if given.
+    fn connect_proxied(
+        &self,
+        url: &Url,
+        proxy: Option<&Proxy>,
+        deadline: Option<Instant>,
+    ) -> io::Result<TcpStream> {
+        if let Some(proxy) = proxy {
+            let stream = self.connect(proxy.url(), deadline)?;
+            self.set_timeouts(&stream, deadline)?;
+            proxy.connect(stream, url)
         } else {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                Error::WrongScheme,
-            ));
+            self.connect(url, deadline)
         }
     }
 
-    fn fetch_network_http<A: ToBody, B: FromBody>(
-        &mut self,
-        request: &mut Request<A>,
-    ) -> io::Result<Response<B>> {
-        let origin = request.url().origin();
-        let mut stream = if let Some(stream) = self.tcp_streams.remove(&origin) {
-            debug!("Reusing connection to {:?}", origin);
-            stream
-        } else {
-            TcpStream::connect(request.url())?
+    fn connect_tls(
+        &self,
+        url: &Url,
+        stream: TcpStream,
+        deadline: Option<Instant>,
+    ) -> io::Result<Box<dyn TlsStream>> {
+        let host = match url.host() {
+            Some(Host::Domain(domain)) => domain.to_string(),
+            Some(Host::Ipv4(ip)) => ip.to_string(),
+            Some(Host::Ipv6(ip)) => ip.to_string(),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, Error::NoDomain)),
         };
-        let response = Client::fetch_data(request, &mut stream)?;
-        if is_persistent_connection(
-            response.version(),
-            response.headers().get_all(header::CONNECTION),
-        ) {
-            debug!("Keeping connection to {:?} for later use", origin);
-            self.tcp_streams.insert(origin, stream);
-        } else {
-            debug!("Closed connection to {:?}", origin);
+        let name = self.inner.config.tls.server_name(&host).unwrap_or(&host).to_string();
+        if name != host {
+            debug!("Verifying {} as {}", host, name);
         }
-        Ok(response)
+        self.set_timeouts(&stream, deadline)?;
+        let stream = self.get_tls_connector()?.connect(&name, stream)?;
+        if let Some(pins) = self.inner.config.tls.pins(&host) {
+            let hashes: Vec<String> = stream
+                .peer_certificates()
+                .iter()
+                .filter_map(|certificate| spki_hash(certificate))
+                .collect();
+            if !hashes.iter().any(|hash| pins.contains(hash)) {
+                warn!("Public keys of {} do not match pins: {:?}", host, hashes);
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    Error::PinMismatch(hashes),
+                ));
+            }
+        }
+        Ok(stream)
     }
 
-    fn fetch_network_https<A: ToBody, B: FromBody>(
-        &mut self,
-        request: &mut Request<A>,
-    ) -> io::Result<Response<B>> {
-        let origin = request.url().origin();
-        let mut tls_stream = if let Some(stream) = self.tls_streams.remove(&origin) {
-            debug!("Reusing connection to {:?}", origin);
-            stream
+    fn connect(&self, url: &Url, deadline: Option<Instant>) -> io::Result<TcpStream> {
+        let timeout = remaining_timeout(self.inner.config.connect_timeout, deadline)?;
+        let timeout = if let Some(timeout) = timeout {
+            timeout
         } else {
-            let domain = if let Some(domain) = request.url().domain() {
-                domain
+            return TcpStream::connect(url);
+        };
+        let mut last_err = None;
+        for addr in url.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => {
+                    debug!("Failed to connect to {}: {}", addr, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
+
+    fn set_timeouts(&self, stream: &TcpStream, deadline: Option<Instant>) -> io::Result<()> {
+        stream.set_read_timeout(remaining_timeout(self.inner.config.read_timeout, deadline)?)?;
+        stream.set_write_timeout(remaining_timeout(self.inner.config.write_timeout, deadline)?)?;
+        Ok(())
+    }
+
+    /// Returns header fields computed by the client for each request sent.
+    fn extra_headers<A: ToBody>(
+        &self,
+        request: &Request<A>,
+        http_proxy: Option<&Proxy>,
+    ) -> io::Result<HeaderMap> {
+        let mut extra = HeaderMap::new();
+        if let Some(auth) = http_proxy.and_then(Proxy::auth) {
+            extra.insert(header::PROXY_AUTHORIZATION, auth);
+        }
+        if !request.headers().contains_key(header::AUTHORIZATION) {
+            if let Some(ref signer) = self.inner.config.aws_signer {
+                for (name, value) in signer.sign(request, SystemTime::now())?.iter() {
+                    extra.insert(name.clone(), value.clone());
+                }
             } else {
-                return Err(io::Error::new(io::ErrorKind::InvalidInput, Error::NoDomain));
-            };
-            let stream = TcpStream::connect(request.url())?;
-            let connector = self.get_tls_connector()?;
-            match connector.connect(domain, stream) {
-                Ok(stream) => stream,
-                Err(HandshakeError::Failure(err)) => return wrap_error(Err(err)),
-                Err(HandshakeError::Interrupted(_)) => {
-                    panic!("TcpStream should never raise WouldBlock.")
+                let mut authenticator =
+                    self.inner.auth.lock().expect("authenticator is not poisoned");
+                if let Some(auth) = authenticator.authorization(request.method(), request.url()) {
+                    extra.insert(header::AUTHORIZATION, auth);
                 }
             }
-        };
-        let response = Client::fetch_data(request, &mut tls_stream)?;
-        if is_persistent_connection(
-            response.version(),
-            response.headers().get_all(header::CONNECTION),
-        ) {
-            debug!("Keeping secure connection to {:?} for later use", origin);
-            self.tls_streams.insert(origin, tls_stream);
-        } else {
-            debug!("Closed secure connection to {:?}", origin);
         }
-        Ok(response)
+        if let Some(ref cookie_jar) = self.inner.cookie_jar {
+            let cookie_jar = cookie_jar.lock().expect("cookie jar is not poisoned");
+            if let Some(cookies) = cookie_jar.cookie_header(request.url()) {
+                // Keep cookies set by the user of the crate.
+                let value = if let Some(value) = request.headers().get(header::COOKIE) {
+                    format!("{}; {}", wrap_error(value.to_str())?, cookies)
+                } else {
+                    cookies
+                };
+                extra.insert(header::COOKIE, wrap_error(HeaderValue::from_str(&value))?);
+            }
+        }
+        Ok(extra)
     }
 
-    fn fetch_data<A: ToBody, B: FromBody, S: Read + Write>(
+    fn fetch_data<A: ToBody>(
+        &self,
         request: &mut Request<A>,
-        mut stream: S,
-    ) -> io::Result<Response<B>> {
+        mut connection: PooledConnection,
+        http_proxy: Option<&Proxy>,
+    ) -> io::Result<Response<Body<PooledConnection>>> {
+        let decompress = self.inner.config.decompress;
+        if decompress && !request.headers().contains_key(header::ACCEPT_ENCODING) {
+            if let Some(value) = accept_encoding() {
+                request.headers_mut().insert(header::ACCEPT_ENCODING, value);
+            }
+        }
         {
-            let mut buf_writer = BufWriter::new(&mut stream);
-            let body = request.body().len();
-            request.headers_mut().insert(header::CONTENT_LENGTH, HeaderValue::from_str(body.to_string().as_str()).expect("integer is valid header"));
-            http1::write_request_header(&mut buf_writer, &request)?;
+            let content_length = request.body().content_length();
+            if let Some(len) = content_length {
+                request.headers_mut().remove(header::TRANSFER_ENCODING);
+                request.headers_mut().insert(
+                    header::CONTENT_LENGTH,
+                    HeaderValue::from_str(len.to_string().as_str())
+                        .expect("integer is valid header"),
+                );
+            } else {
+                request.headers_mut().remove(header::CONTENT_LENGTH);
+                request.headers_mut().insert(
+                    header::TRANSFER_ENCODING,
+                    HeaderValue::from_static("chunked"),
+                );
+            }
+            let extra = self.extra_headers(request, http_proxy)?;
+            let mut buf_writer = BufWriter::new(&mut connection);
+            let absolute_form = http_proxy.is_some();
+            http1::write_request_header(&mut buf_writer, request, &extra, absolute_form)?;
+            if content_length.is_some() {
+                request.body_mut().write_body(&mut buf_writer)?;
+            } else {
+                let mut chunked_writer = ChunkedWriter::new(&mut buf_writer);
+                request.body_mut().write_body(&mut chunked_writer)?;
+                chunked_writer.finish()?;
+            }
             buf_writer.flush()?;
-            buf_writer.write_all(request.body().to_body())?;
         }
-        let mut buf_reader = BufReader::new(&mut stream);
-        let parts = http1::read_response_header(&mut buf_reader)?;
-        let mut body = Body::from_response(buf_reader, &parts, request.method() == &Method::HEAD)?;
-        let typed_body = FromBody::from_body(&parts, &mut body)?;
-        Ok(Response::from_parts(parts, typed_body))
+        let mut buf_reader = BufReader::new(connection);
+        let mut parts = http1::read_response_header(&mut buf_reader)?;
+        let persistent =
+            is_persistent_connection(parts.version, parts.headers.get_all(header::CONNECTION));
+        buf_reader.get_mut().set_reusable(persistent);
+        let finished = buf_reader.get_ref().finished();
+        let body = Body::from_response(
+            buf_reader,
+            &mut parts,
+            request.method() == &Method::HEAD,
+            finished,
+            decompress,
+            self.inner.config.text,
+        )?;
+        Ok(Response::from_parts(parts, body))
     }
 }
 
@@ -220,43 +879,207 @@ impl Default for Client {
 /// (They are here for information and logging
 /// and should not be dependent on in code.)
 #[derive(Debug)]
+#[allow(clippy::manual_non_exhaustive)]
 pub enum Error {
     /// An invalid scheme was encountered in a request URL.
     ///
     /// Currently allowed are only HTTP and HTTPS.
     WrongScheme,
-    /// URL contains no domain.
+    /// URL contains no host.
     ///
-    /// TLS requires a domain name to verify the certificate.
-    /// If the URL contains an IP address it has no domain.
+    /// TLS requires a domain name or an IP address
+    /// to verify the certificate.
     NoDomain,
     /// The client tried to follow too many redirects and gave up.
     ///
-    /// Current limit is 20 redirects maximum.
+    /// The limit is set with `RedirectPolicy::limited`,
+    /// by default it is 20 redirects.
     TooManyRedirects,
     /// There was some logic error in the response received.
     ///
     /// Such problems may not always raise this error but
     /// instead provide more specific information from the original error.
     BadResponse,
+    /// The proxy server did not open a tunnel to the origin server.
+    ///
+    /// The proxy may require authentication or
+    /// may not allow connections to the origin server.
+    /// This error is raised for HTTP and SOCKS proxies.
+    ProxyTunnel,
+    /// The request body could not be sent again.
+    ///
+    /// Redirects with the status 307 or 308 require the body to be sent
+    /// again. Streams must be made rewindable for this to work,
+    /// see `StreamBody::rewindable`.
+    UnrewindableBody,
+    /// A connect, read or write timeout or the request deadline expired.
+    ///
+    /// The timeouts are configured with the `ClientBuilder`.
+    TimedOut,
+    /// No public key of the server matched the pins of the host.
+    ///
+    /// Contains the base64 encoded SHA-256 hashes of the public keys
+    /// presented by the server, leaf first, to help rotating pins.
+    /// Pins are set with `ClientBuilder::pin_public_keys`.
+    PinMismatch(Vec<String>),
+    /// The request body could not be signed.
+    ///
+    /// AWS Signature Version 4 requires the length of the body,
+    /// streams must be created with `StreamBody::with_length`
+    /// to be sent with an `AwsSigner`.
+    UnsignableBody,
     #[doc(hidden)]
     __Nonexhaustive,
 }
 
-impl ::std::error::Error for Error {
-    fn description(&self) -> &str {
-        match *self {
+impl ::std::error::Error for Error {}
+
+impl ::std::fmt::Display for Error {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.write_str(match *self {
             Error::WrongScheme => "request URL has an unsupported scheme",
-            Error::NoDomain => "URL contains no domain for TLS connection",
+            Error::NoDomain => "URL contains no host for TLS connection",
             Error::TooManyRedirects => "encountered too many redirects",
             Error::BadResponse => "bad response received",
+            Error::ProxyTunnel => "proxy server refused to connect to origin server",
+            Error::UnrewindableBody => "request body cannot be sent again",
+            Error::TimedOut => "operation timed out",
+            Error::PinMismatch(_) => "server public key does not match pins",
+            Error::UnsignableBody => "request body of unknown length cannot be signed",
             _ => panic!(),
+        })?;
+        if let Error::PinMismatch(ref hashes) = *self {
+            write!(f, ", presented: {}", hashes.join(", "))?;
         }
+        Ok(())
     }
 }
 
-impl ::std::fmt::Display for Error {
-    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-        f.write_str(::std::error::Error::description(self))
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    /// Answers each request head received on a local port with `handler`.
+    fn serve<F>(handler: F) -> Url
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let handler = Arc::new(handler);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let handler = handler.clone();
+                thread::spawn(move || loop {
+                    let mut head = String::new();
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                            return;
+                        }
+                        head.push_str(&line);
+                        if line == "\r\n" {
+                            break;
+                        }
+                    }
+                    if stream.write_all(handler(&head).as_bytes()).is_err() {
+                        return;
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    fn redirect(location: &str) -> String {
+        format!(
+            "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n",
+            location
+        )
+    }
+
+    fn ok(body: &str) -> String {
+        format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn path(head: &str) -> &str {
+        head.split(' ').nth(1).unwrap()
+    }
+
+    #[test]
+    fn follows_long_redirect_chains() {
+        let url = serve(|head| {
+            let hop: usize = path(head)[1..].parse().unwrap();
+            if hop < 5000 {
+                redirect(&format!("/{}", hop + 1))
+            } else {
+                ok("done")
+            }
+        });
+        let client = Client::builder()
+            .proxy_from_env(false)
+            .redirect(RedirectPolicy::limited(usize::MAX))
+            .build();
+        let response: Response<String> = client
+            .fetch(Request::new(url.join("/0").unwrap(), ()))
+            .unwrap();
+        assert_eq!(response.body(), "done");
+        let history = response.extensions().get::<RedirectHistory>().unwrap();
+        assert_eq!(history.hops().len(), 5000);
+    }
+
+    #[test]
+    fn rejects_malformed_pins() {
+        let hash = base64::encode(&[0; 32]);
+        let mut builder = Client::builder();
+        assert!(builder.pin_public_keys("example.com", &[&hash]).is_ok());
+        let pin = format!("sha256/{}", hash);
+        assert!(builder.pin_public_keys("example.com", &[&pin]).is_ok());
+        for pin in &["not base64!", "AAAA", ""] {
+            let err = builder.pin_public_keys("example.com", &[pin]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    fn fetch_with_credentials(client: &Client, url: Url) -> String {
+        let request = Request::builder(url)
+            .header(header::AUTHORIZATION, "Basic dXNlcjpwYXNz")
+            .header(header::COOKIE, "session=secret")
+            .header(header::PROXY_AUTHORIZATION, "Basic dXNlcjpwYXNz")
+            .body(())
+            .unwrap();
+        let response: Response<String> = client.fetch(request).unwrap();
+        response.into_body().to_lowercase()
+    }
+
+    #[test]
+    fn strips_sensitive_headers_on_cross_origin_redirects() {
+        let echo = serve(ok);
+        let other = echo.join("/echo").unwrap();
+        let url = serve(move |head| match path(head) {
+            "/other" => redirect(other.as_str()),
+            "/same" => redirect("/echo"),
+            _ => ok(head),
+        });
+        let client = Client::builder().proxy_from_env(false).build();
+        let names = ["authorization:", "cookie:", "proxy-authorization:"];
+
+        let head = fetch_with_credentials(&client, url.join("/other").unwrap());
+        assert!(head.starts_with("get /echo "));
+        for name in &names {
+            assert!(!head.contains(name), "{} sent to other origin", name);
+        }
+
+        let head = fetch_with_credentials(&client, url.join("/same").unwrap());
+        assert!(head.starts_with("get /echo "));
+        for name in &names {
+            assert!(head.contains(name), "{} not sent to same origin", name);
+        }
     }
 }
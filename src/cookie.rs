@@ -0,0 +1,782 @@
+use std::cmp::Ordering;
+#[cfg(feature = "json")]
+use std::io::Read;
+use std::io::{self, BufRead, Write};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use http::Url;
+use http::header::{self, HeaderMap};
+#[cfg(feature = "json")]
+use serde_json::{self, Value};
+
+use util::{civil_from_days, days_from_civil};
+#[cfg(feature = "json")]
+use util::wrap_error;
+
+/// Cookies are kept at most 400 days.
+const MAX_AGE_LIMIT: u64 = 400 * 24 * 60 * 60;
+
+/// The `SameSite` attribute of a cookie.
+///
+/// The client stores the attribute but does not restrict cookies
+/// based on it as there are no cross-site requests outside a browser.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// A cookie stored in a `CookieJar`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    host_only: bool,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+    expires: Option<SystemTime>,
+    creation: SystemTime,
+}
+
+impl Cookie {
+    /// Returns the name of the cookie.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the value of the cookie.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Returns the host or domain the cookie is sent to.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Returns true if the cookie is only sent to the exact host
+    /// and not to its subdomains.
+    ///
+    /// This is the case for cookies without a `Domain` attribute.
+    pub fn host_only(&self) -> bool {
+        self.host_only
+    }
+
+    /// Returns the path prefix of URLs the cookie is sent to.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns true if the cookie is only sent over secure connections.
+    pub fn secure(&self) -> bool {
+        self.secure
+    }
+
+    /// Returns true if the cookie is hidden from scripts in a browser.
+    pub fn http_only(&self) -> bool {
+        self.http_only
+    }
+
+    /// Returns the `SameSite` attribute if present.
+    pub fn same_site(&self) -> Option<SameSite> {
+        self.same_site
+    }
+
+    /// Returns the time the cookie expires.
+    ///
+    /// Session cookies without an expiry time return `None`.
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.expires
+    }
+
+    /// Returns true if the cookie has expired at the given time.
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.map_or(false, |expires| expires <= now)
+    }
+
+    /// Parses a `Set-Cookie` header value received from `url`.
+    ///
+    /// Implements the parsing and storage model of
+    /// [RFC 6265](https://httpwg.org/specs/rfc6265.html#storage-model).
+    /// Returns `None` if the cookie must be ignored.
+    fn parse(url: &Url, set_cookie: &str, now: SystemTime) -> Option<Cookie> {
+        let host = canonical_host(url)?;
+        let mut parts = set_cookie.split(';');
+        let mut pair = parts.next()?.splitn(2, '=');
+        let name = pair.next()?.trim();
+        let value = pair.next()?.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = Cookie {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            secure: false,
+            http_only: false,
+            same_site: None,
+            expires: None,
+            creation: now,
+        };
+        let mut domain = None;
+        let mut max_age = None;
+        let mut expires = None;
+        for attribute in parts {
+            let mut attribute = attribute.splitn(2, '=');
+            let name = attribute.next().unwrap_or("").trim().to_ascii_lowercase();
+            let value = attribute.next().unwrap_or("").trim();
+            match name.as_str() {
+                "expires" => {
+                    if let Some(time) = parse_cookie_date(value) {
+                        expires = Some(time);
+                    }
+                }
+                "max-age" => {
+                    if let Some(time) = parse_max_age(value, now) {
+                        max_age = Some(time);
+                    }
+                }
+                "domain" => {
+                    let value = value.trim_start_matches('.').to_ascii_lowercase();
+                    if !value.is_empty() {
+                        domain = Some(value);
+                    }
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_owned(),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "samesite" => {
+                    cookie.same_site = match value.to_ascii_lowercase().as_str() {
+                        "strict" => Some(SameSite::Strict),
+                        "lax" => Some(SameSite::Lax),
+                        "none" => Some(SameSite::None),
+                        _ => cookie.same_site,
+                    }
+                }
+                _ => (),
+            }
+        }
+        // Max-Age has precedence over Expires.
+        cookie.expires = max_age.or(expires).map(|time| {
+            let limit = now + Duration::from_secs(MAX_AGE_LIMIT);
+            if time > limit {
+                limit
+            } else {
+                time
+            }
+        });
+        if let Some(domain) = domain {
+            if !domain_match(&host, &domain) {
+                return None;
+            }
+            // Without a public suffix list at least reject
+            // cookies for top level domains.
+            if !domain.contains('.') && domain != host {
+                return None;
+            }
+            cookie.domain = domain;
+            cookie.host_only = false;
+        }
+        if cookie.secure && url.scheme() != "https" {
+            return None;
+        }
+        Some(cookie)
+    }
+
+    fn matches(&self, host: &str, path: &str, secure: bool, now: SystemTime) -> bool {
+        let domain_matches = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(host, &self.domain)
+        };
+        domain_matches && path_match(path, &self.path) && (secure || !self.secure)
+            && !self.is_expired(now)
+    }
+}
+
+/// Stores cookies received from servers and attaches them to requests.
+///
+/// Enable it with `ClientBuilder::cookie_store` to keep sessions
+/// across requests and redirects. Cookies are matched to requests
+/// following [RFC 6265](https://httpwg.org/specs/rfc6265.html)
+/// without checking a public suffix list.
+///
+/// Cookies can be saved to and loaded from files in the Netscape
+/// `cookies.txt` format and, with the `json` cargo feature, as JSON.
+#[derive(Clone, Debug, Default)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    /// Creates an empty cookie jar.
+    pub fn new() -> CookieJar {
+        CookieJar::default()
+    }
+
+    /// Returns all cookies stored in the jar.
+    pub fn cookies(&self) -> &[Cookie] {
+        &self.cookies
+    }
+
+    /// Removes all cookies.
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+
+    /// Removes all cookies that have expired.
+    pub fn remove_expired(&mut self) {
+        let now = SystemTime::now();
+        self.cookies.retain(|cookie| !cookie.is_expired(now));
+    }
+
+    /// Stores the cookie of a `Set-Cookie` header value received from `url`.
+    ///
+    /// Invalid cookies and cookies the server at `url` is not allowed
+    /// to set are ignored. A cookie with the same name, domain and path
+    /// replaces an existing cookie, an expired cookie removes it.
+    pub fn set_cookie(&mut self, url: &Url, set_cookie: &str) {
+        let now = SystemTime::now();
+        let mut cookie = if let Some(cookie) = Cookie::parse(url, set_cookie, now) {
+            cookie
+        } else {
+            debug!("Ignored cookie {:?} from {}", set_cookie, url);
+            return;
+        };
+        if let Some(old) = self.remove_same(&cookie) {
+            cookie.creation = old.creation;
+        }
+        if !cookie.is_expired(now) {
+            self.cookies.push(cookie);
+        }
+    }
+
+    /// Removes and returns the cookie with the same name, domain and path.
+    fn remove_same(&mut self, cookie: &Cookie) -> Option<Cookie> {
+        let index = self.cookies.iter().position(|c| {
+            c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path
+        })?;
+        Some(self.cookies.remove(index))
+    }
+
+    /// Loads cookies in the Netscape `cookies.txt` format used by curl and wget.
+    ///
+    /// Expired cookies are skipped. Session cookies, stored with an
+    /// expiry time of 0, are only loaded if `keep_session` is true.
+    /// Loaded cookies replace cookies with the same name, domain and path.
+    /// Malformed lines are ignored.
+    pub fn load_netscape<R: BufRead>(&mut self, reader: R, keep_session: bool) -> io::Result<()> {
+        let now = SystemTime::now();
+        for line in reader.lines() {
+            let line = line?;
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(line) => (line, true),
+                None => (&line[..], false),
+            };
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 7 {
+                warn!("Ignored malformed line in cookie file: {:?}", line);
+                continue;
+            }
+            let expires = if let Ok(expires) = fields[4].parse::<u64>() {
+                expires
+            } else {
+                warn!("Ignored malformed line in cookie file: {:?}", line);
+                continue;
+            };
+            let cookie = Cookie {
+                name: fields[5].to_owned(),
+                value: fields[6].to_owned(),
+                domain: fields[0].trim_start_matches('.').to_ascii_lowercase(),
+                host_only: !fields[1].eq_ignore_ascii_case("TRUE"),
+                path: fields[2].to_owned(),
+                secure: fields[3].eq_ignore_ascii_case("TRUE"),
+                http_only,
+                same_site: None,
+                expires: if expires == 0 {
+                    None
+                } else {
+                    Some(UNIX_EPOCH + Duration::from_secs(expires))
+                },
+                creation: now,
+            };
+            self.load(cookie, now, keep_session);
+        }
+        Ok(())
+    }
+
+    /// Saves the cookies in the Netscape `cookies.txt` format used by curl and wget.
+    ///
+    /// Expired cookies are skipped. Session cookies are only
+    /// saved if `keep_session` is true.
+    pub fn save_netscape<W: Write>(&self, mut writer: W, keep_session: bool) -> io::Result<()> {
+        let now = SystemTime::now();
+        writeln!(writer, "# Netscape HTTP Cookie File")?;
+        for cookie in self.saved_cookies(now, keep_session) {
+            writeln!(
+                writer,
+                "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                if cookie.http_only { "#HttpOnly_" } else { "" },
+                if cookie.host_only { "" } else { "." },
+                cookie.domain,
+                if cookie.host_only { "FALSE" } else { "TRUE" },
+                cookie.path,
+                if cookie.secure { "TRUE" } else { "FALSE" },
+                cookie.expires.map_or(0, unix_time),
+                cookie.name,
+                cookie.value
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Loads cookies saved with `save_json`.
+    ///
+    /// Expired cookies are skipped. Session cookies are only
+    /// loaded if `keep_session` is true. Loaded cookies replace
+    /// cookies with the same name, domain and path.
+    #[cfg(feature = "json")]
+    pub fn load_json<R: Read>(&mut self, reader: R, keep_session: bool) -> io::Result<()> {
+        let now = SystemTime::now();
+        let values: Vec<Value> = wrap_error(serde_json::from_reader(reader))?;
+        for value in values {
+            if let Some(cookie) = cookie_from_json(&value, now) {
+                self.load(cookie, now, keep_session);
+            } else {
+                warn!("Ignored malformed cookie in cookie file: {}", value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the cookies as a JSON array of objects.
+    ///
+    /// Expired cookies are skipped. Session cookies are only
+    /// saved if `keep_session` is true.
+    #[cfg(feature = "json")]
+    pub fn save_json<W: Write>(&self, writer: W, keep_session: bool) -> io::Result<()> {
+        let now = SystemTime::now();
+        let values: Vec<Value> = self.saved_cookies(now, keep_session)
+            .map(cookie_to_json)
+            .collect();
+        wrap_error(serde_json::to_writer_pretty(writer, &values))
+    }
+
+    fn load(&mut self, cookie: Cookie, now: SystemTime, keep_session: bool) {
+        if cookie.is_expired(now) || (cookie.expires.is_none() && !keep_session) {
+            return;
+        }
+        self.remove_same(&cookie);
+        self.cookies.push(cookie);
+    }
+
+    fn saved_cookies<'a>(
+        &'a self,
+        now: SystemTime,
+        keep_session: bool,
+    ) -> Box<dyn Iterator<Item = &'a Cookie> + 'a> {
+        Box::new(self.cookies.iter().filter(move |cookie| {
+            !cookie.is_expired(now) && (cookie.expires.is_some() || keep_session)
+        }))
+    }
+
+    /// Returns the value of the `Cookie` header for a request to `url`.
+    ///
+    /// Returns `None` if no cookies match.
+    pub fn cookie_header(&self, url: &Url) -> Option<String> {
+        let host = canonical_host(url)?;
+        let secure = url.scheme() == "https";
+        let now = SystemTime::now();
+        let mut cookies: Vec<&Cookie> = self.cookies
+            .iter()
+            .filter(|cookie| cookie.matches(&host, url.path(), secure, now))
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        // Longer paths first, then older cookies first.
+        cookies.sort_by(|a, b| match b.path.len().cmp(&a.path.len()) {
+            Ordering::Equal => a.creation.cmp(&b.creation),
+            ordering => ordering,
+        });
+        let pairs: Vec<String> = cookies
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        Some(pairs.join("; "))
+    }
+
+    /// Stores all cookies set in a response from `url`.
+    pub(crate) fn store_response(&mut self, url: &Url, headers: &HeaderMap) {
+        for value in headers.get_all(header::SET_COOKIE) {
+            if let Ok(value) = value.to_str() {
+                self.set_cookie(url, value);
+            }
+        }
+    }
+}
+
+fn canonical_host(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    Some(host.trim_matches(|c| c == '[' || c == ']').to_ascii_lowercase())
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    // https://httpwg.org/specs/rfc6265.html#cookie-domain
+    if host == domain {
+        return true;
+    }
+    host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.')
+        && host.parse::<IpAddr>().is_err()
+}
+
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    // https://httpwg.org/specs/rfc6265.html#cookie-path
+    if request_path == cookie_path {
+        return true;
+    }
+    request_path.starts_with(cookie_path)
+        && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/'))
+}
+
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    if !path.starts_with('/') {
+        return "/".to_owned();
+    }
+    match path.rfind('/') {
+        Some(0) | None => "/".to_owned(),
+        Some(index) => path[..index].to_owned(),
+    }
+}
+
+fn parse_max_age(value: &str, now: SystemTime) -> Option<SystemTime> {
+    let first = value.chars().next()?;
+    if !(first.is_ascii_digit() || first == '-') {
+        return None;
+    }
+    if let Some(digits) = value.strip_prefix('-') {
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        return Some(UNIX_EPOCH);
+    }
+    if !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    match value.parse::<u64>() {
+        Ok(0) => Some(UNIX_EPOCH),
+        Ok(seconds) => Some(now + Duration::from_secs(seconds.min(MAX_AGE_LIMIT))),
+        // The number is too large to parse.
+        Err(_) => Some(now + Duration::from_secs(MAX_AGE_LIMIT)),
+    }
+}
+
+/// Parses a date with the algorithm in
+/// [RFC 6265 section 5.1.1](https://httpwg.org/specs/rfc6265.html#cookie-date).
+pub(crate) fn parse_cookie_date(value: &str) -> Option<SystemTime> {
+    let is_delimiter = |c: char| {
+        c == '\t' || (' '..='/').contains(&c) || (';'..='@').contains(&c)
+            || ('['..='`').contains(&c) || ('{'..='~').contains(&c)
+    };
+    let mut time = None;
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+    for token in value.split(is_delimiter).filter(|t| !t.is_empty()) {
+        if time.is_none() {
+            if let Some(hms) = parse_time(token) {
+                time = Some(hms);
+                continue;
+            }
+        }
+        if day.is_none() {
+            if let Some(d) = parse_digits(token, 1, 2) {
+                day = Some(d);
+                continue;
+            }
+        }
+        if month.is_none() {
+            if let Some(m) = parse_month(token) {
+                month = Some(m);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some(y) = parse_digits(token, 2, 4) {
+                year = Some(y);
+                continue;
+            }
+        }
+    }
+    let (hour, minute, second) = time?;
+    let (day, month, mut year) = (day?, month?, year?);
+    if (70..=99).contains(&year) {
+        year += 1900;
+    } else if year <= 69 {
+        year += 2000;
+    }
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let days = days_from_civil(year as i64, month, day);
+    // Reject days after the end of the month like February 30.
+    if civil_from_days(days) != (year as i64, month, day) {
+        return None;
+    }
+    let seconds = days * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+    if seconds < 0 {
+        // Dates before 1970 are in the past anyway.
+        return Some(UNIX_EPOCH);
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(seconds as u64))
+}
+
+/// Parses the leading digits of a token.
+///
+/// The digits may be followed by other characters
+/// which are ignored.
+fn parse_digits(token: &str, min: usize, max: usize) -> Option<u32> {
+    let len = token.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if len < min || len > max {
+        return None;
+    }
+    token[..len].parse().ok()
+}
+
+fn parse_time(token: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = token.splitn(3, ':');
+    let hour = parts.next()?;
+    let minute = parts.next()?;
+    let second = parts.next()?;
+    if hour.len() > 2 || minute.len() > 2 {
+        return None;
+    }
+    Some((
+        parse_digits(hour, 1, 2)?,
+        parse_digits(minute, 1, 2)?,
+        parse_digits(second, 1, 2)?,
+    ))
+}
+
+fn parse_month(token: &str) -> Option<u32> {
+    let prefix = token.get(..3)?.to_ascii_lowercase();
+    let months = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"
+    ];
+    months
+        .iter()
+        .position(|m| *m == prefix)
+        .map(|index| index as u32 + 1)
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(feature = "json")]
+fn cookie_to_json(cookie: &Cookie) -> Value {
+    let same_site = cookie.same_site.map(|same_site| match same_site {
+        SameSite::Strict => "Strict",
+        SameSite::Lax => "Lax",
+        SameSite::None => "None",
+    });
+    json!({
+        "name": cookie.name,
+        "value": cookie.value,
+        "domain": cookie.domain,
+        "host_only": cookie.host_only,
+        "path": cookie.path,
+        "secure": cookie.secure,
+        "http_only": cookie.http_only,
+        "same_site": same_site,
+        "expires": cookie.expires.map(unix_time),
+        "creation": unix_time(cookie.creation),
+    })
+}
+
+#[cfg(feature = "json")]
+fn cookie_from_json(value: &Value, now: SystemTime) -> Option<Cookie> {
+    let string = |key: &str| value.get(key).and_then(Value::as_str).map(str::to_owned);
+    let flag = |key: &str| value.get(key).and_then(Value::as_bool);
+    let time = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_u64)
+            .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds))
+    };
+    let same_site = match value.get("same_site").and_then(Value::as_str) {
+        Some("Strict") => Some(SameSite::Strict),
+        Some("Lax") => Some(SameSite::Lax),
+        Some("None") => Some(SameSite::None),
+        _ => None,
+    };
+    Some(Cookie {
+        name: string("name")?,
+        value: string("value")?,
+        domain: string("domain")?.to_ascii_lowercase(),
+        host_only: flag("host_only").unwrap_or(true),
+        path: string("path").unwrap_or_else(|| "/".to_owned()),
+        secure: flag("secure").unwrap_or(false),
+        http_only: flag("http_only").unwrap_or(false),
+        same_site,
+        expires: time("expires"),
+        creation: time("creation").unwrap_or(now),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(value: &str) -> Option<u64> {
+        parse_cookie_date(value).map(unix_time)
+    }
+
+    #[test]
+    fn parses_cookie_dates() {
+        // The formats in RFC 2616 section 3.3.1.
+        assert_eq!(date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784111777));
+        assert_eq!(date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(784111777));
+        assert_eq!(date("Sun Nov  6 08:49:37 1994"), Some(784111777));
+        assert_eq!(date("Wed, 09 Jun 2021 10:18:14 GMT"), Some(1623233894));
+        assert_eq!(date("01 jan 00 00:00:00"), Some(946684800));
+        assert_eq!(date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(date("Mon, 01 Jan 1900 00:00:00 GMT"), Some(0));
+    }
+
+    #[test]
+    fn rejects_invalid_cookie_dates() {
+        assert_eq!(date(""), None);
+        assert_eq!(date("Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(date("Sun, 06 Nov 1994 GMT"), None);
+        assert_eq!(date("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(date("Sun, 06 Nov 1994 24:00:00 GMT"), None);
+        assert_eq!(date("Sun, 32 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(date("Tue, 30 Feb 2021 00:00:00 GMT"), None);
+        assert_eq!(date("Mon, 01 Jan 1600 00:00:00 GMT"), None);
+    }
+
+    #[test]
+    fn matches_domains() {
+        assert!(domain_match("example.com", "example.com"));
+        assert!(domain_match("www.example.com", "example.com"));
+        assert!(!domain_match("wwwexample.com", "example.com"));
+        assert!(!domain_match("example.com", "www.example.com"));
+        assert!(!domain_match("192.168.0.1", "168.0.1"));
+    }
+
+    #[test]
+    fn matches_paths() {
+        assert!(path_match("/", "/"));
+        assert!(path_match("/docs", "/docs"));
+        assert!(path_match("/docs/web", "/docs"));
+        assert!(path_match("/docs/web", "/docs/"));
+        assert!(!path_match("/docsets", "/docs"));
+        assert!(!path_match("/", "/docs"));
+    }
+
+    #[test]
+    fn uses_directory_as_default_path() {
+        let path = |url: &str| default_path(&Url::parse(url).unwrap());
+        assert_eq!(path("http://example.com"), "/");
+        assert_eq!(path("http://example.com/page"), "/");
+        assert_eq!(path("http://example.com/docs/page"), "/docs");
+        assert_eq!(path("http://example.com/docs/web/"), "/docs/web");
+    }
+
+    #[test]
+    fn sends_cookies_to_matching_hosts() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        let mut jar = CookieJar::new();
+        jar.set_cookie(&url("http://example.com/"), "host=1");
+        jar.set_cookie(&url("http://example.com/"), "domain=2; Domain=.Example.com");
+        assert_eq!(
+            jar.cookie_header(&url("http://example.com/")),
+            Some("host=1; domain=2".to_owned())
+        );
+        assert_eq!(
+            jar.cookie_header(&url("http://www.example.com/")),
+            Some("domain=2".to_owned())
+        );
+        assert_eq!(jar.cookie_header(&url("http://example.org/")), None);
+    }
+
+    #[test]
+    fn rejects_foreign_domains() {
+        let url = Url::parse("http://www.example.com/").unwrap();
+        let mut jar = CookieJar::new();
+        jar.set_cookie(&url, "a=1; Domain=example.org");
+        jar.set_cookie(&url, "b=2; Domain=com");
+        jar.set_cookie(&url, "c=3; Domain=sub.www.example.com");
+        jar.set_cookie(&url, "d=4; Secure");
+        assert!(jar.cookies().is_empty());
+    }
+
+    #[test]
+    fn orders_and_replaces_cookies() {
+        let url = Url::parse("https://example.com/docs/web").unwrap();
+        let mut jar = CookieJar::new();
+        jar.set_cookie(&url, "a=1; Path=/");
+        jar.set_cookie(&url, "b=2; Path=/docs");
+        jar.set_cookie(&url, "a=3; Path=/");
+        assert_eq!(jar.cookie_header(&url), Some("b=2; a=3".to_owned()));
+        jar.set_cookie(&url, "b=; Path=/docs; Max-Age=0");
+        assert_eq!(jar.cookie_header(&url), Some("a=3".to_owned()));
+    }
+
+    #[test]
+    fn prefers_max_age_over_expires() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let now = SystemTime::now();
+        let cookie = Cookie::parse(
+            &url,
+            "a=1; Max-Age=60; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+            now,
+        ).unwrap();
+        assert_eq!(cookie.expires(), Some(now + Duration::from_secs(60)));
+        let cookie = Cookie::parse(&url, "a=1; Max-Age=999999999999999999999", now).unwrap();
+        assert_eq!(cookie.expires(), Some(now + Duration::from_secs(MAX_AGE_LIMIT)));
+    }
+
+    #[test]
+    fn round_trips_netscape_files() {
+        let file = "# Netscape HTTP Cookie File\n\
+                    .example.com\tTRUE\t/\tFALSE\t4102444800\tdomain\t1\n\
+                    #HttpOnly_example.com\tFALSE\t/docs\tTRUE\t4102444800\thost\t2\n\
+                    example.com\tFALSE\t/\tFALSE\t0\tsession\t3\n\
+                    example.com\tFALSE\t/\tFALSE\t1\texpired\t4\n\
+                    malformed line\n";
+        let mut jar = CookieJar::new();
+        jar.load_netscape(file.as_bytes(), false).unwrap();
+        let names: Vec<&str> = jar.cookies().iter().map(Cookie::name).collect();
+        assert_eq!(names, ["domain", "host"]);
+        assert!(!jar.cookies()[0].host_only());
+        assert!(jar.cookies()[1].http_only() && jar.cookies()[1].secure());
+
+        let mut saved = Vec::new();
+        jar.save_netscape(&mut saved, false).unwrap();
+        let mut loaded = CookieJar::new();
+        loaded.load_netscape(&saved[..], false).unwrap();
+        assert_eq!(loaded.cookies().len(), 2);
+        for (a, b) in jar.cookies().iter().zip(loaded.cookies()) {
+            assert_eq!((a.name(), a.domain(), a.path()), (b.name(), b.domain(), b.path()));
+            assert_eq!(a.host_only(), b.host_only());
+            assert_eq!((a.secure(), a.http_only()), (b.secure(), b.http_only()));
+            assert_eq!(a.expires(), b.expires());
+        }
+    }
+}
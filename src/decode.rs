@@ -0,0 +1,295 @@
+use std::io::{self, Read};
+
+#[cfg(feature = "brotli")]
+use brotli_decompressor::Decompressor;
+#[cfg(feature = "gzip")]
+use flate2::read::GzDecoder;
+#[cfg(feature = "deflate")]
+use flate2::read::ZlibDecoder;
+use http::header::{self, HeaderMap, HeaderValue};
+use http::response::Parts;
+
+/// Removes content codings from a message body.
+///
+/// Stacked codings are decoded by nesting decoders,
+/// the outermost decoder removes the coding applied first.
+pub(crate) enum Decoder<R: Read> {
+    Identity(R),
+    #[cfg(feature = "gzip")]
+    Gzip(Box<GzDecoder<Input<R>>>),
+    #[cfg(feature = "deflate")]
+    Deflate(Box<ZlibDecoder<Input<R>>>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<Decompressor<Input<R>>>),
+}
+
+/// The encoded input of a decoder.
+///
+/// Remembers if the input ended before any data was read,
+/// an empty input is an empty body and not a truncated stream.
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+pub(crate) struct Input<R: Read> {
+    inner: Box<Decoder<R>>,
+    started: bool,
+    empty: bool,
+}
+
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+impl<R: Read> Input<R> {
+    fn new(decoder: Decoder<R>) -> Input<R> {
+        Input {
+            inner: Box::new(decoder),
+            started: false,
+            empty: false,
+        }
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+impl<R: Read> Read for Input<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_len = self.inner.read(buf)?;
+        if read_len == 0 && !buf.is_empty() {
+            self.empty = !self.started;
+        }
+        self.started |= read_len > 0;
+        Ok(read_len)
+    }
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a decoder for all codings listed in `Content-Encoding`.
+    ///
+    /// If the body is decoded the `Content-Encoding` and `Content-Length`
+    /// headers are removed as they no longer describe the body.
+    /// If any coding is not supported the body is left untouched.
+    pub(crate) fn from_response(reader: R, response: &mut Parts) -> Decoder<R> {
+        let codings = if let Some(codings) = content_codings(&response.headers) {
+            codings
+        } else {
+            return Decoder::Identity(reader);
+        };
+        if codings.is_empty() {
+            return Decoder::Identity(reader);
+        }
+        if let Some(coding) = codings.iter().find(|c| !is_supported(c)) {
+            warn!("Unsupported content coding {:?}, body is not decoded", coding);
+            return Decoder::Identity(reader);
+        }
+        debug!("Decoding body with content codings {:?}", codings);
+        response.headers.remove(header::CONTENT_ENCODING);
+        response.headers.remove(header::CONTENT_LENGTH);
+        let mut decoder = Decoder::Identity(reader);
+        for coding in codings.iter().rev() {
+            decoder = decoder.wrap(coding);
+        }
+        decoder
+    }
+
+    fn wrap(self, coding: &str) -> Decoder<R> {
+        match coding {
+            #[cfg(feature = "gzip")]
+            "gzip" | "x-gzip" => Decoder::Gzip(Box::new(GzDecoder::new(Input::new(self)))),
+            #[cfg(feature = "deflate")]
+            "deflate" => Decoder::Deflate(Box::new(ZlibDecoder::new(Input::new(self)))),
+            #[cfg(feature = "brotli")]
+            "br" => Decoder::Brotli(Box::new(Decompressor::new(Input::new(self), 4096))),
+            _ => unreachable!("coding is supported"),
+        }
+    }
+
+    /// Returns the undecoded reader.
+    pub(crate) fn get_mut(&mut self) -> &mut R {
+        match *self {
+            Decoder::Identity(ref mut reader) => reader,
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(ref mut decoder) => decoder.get_mut().inner.get_mut(),
+            #[cfg(feature = "deflate")]
+            Decoder::Deflate(ref mut decoder) => decoder.get_mut().inner.get_mut(),
+            #[cfg(feature = "brotli")]
+            Decoder::Brotli(ref mut decoder) => decoder.get_mut().inner.get_mut(),
+        }
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Decoder::Identity(ref mut reader) => reader.read(buf),
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(ref mut decoder) => {
+                let result = decoder.read(buf);
+                empty_if_no_input(result, decoder.get_ref())
+            }
+            #[cfg(feature = "deflate")]
+            Decoder::Deflate(ref mut decoder) => {
+                let result = decoder.read(buf);
+                empty_if_no_input(result, decoder.get_ref())
+            }
+            #[cfg(feature = "brotli")]
+            Decoder::Brotli(ref mut decoder) => {
+                let result = decoder.read(buf);
+                empty_if_no_input(result, decoder.get_ref())
+            }
+        }
+    }
+}
+
+/// Treats a decoder error caused by an empty input as an empty body.
+///
+/// Servers send empty bodies with `Content-Encoding` for example
+/// with `Content-Length: 0`, the decoders reject them as invalid.
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+fn empty_if_no_input<R: Read>(result: io::Result<usize>, input: &Input<R>) -> io::Result<usize> {
+    match result {
+        Err(_) if input.empty => Ok(0),
+        result => result,
+    }
+}
+
+/// Returns the value for the `Accept-Encoding` header
+/// listing all content codings enabled at compile time.
+pub(crate) fn accept_encoding() -> Option<HeaderValue> {
+    let mut codings = Vec::new();
+    if cfg!(feature = "gzip") {
+        codings.push("gzip");
+    }
+    if cfg!(feature = "deflate") {
+        codings.push("deflate");
+    }
+    if cfg!(feature = "brotli") {
+        codings.push("br");
+    }
+    if codings.is_empty() {
+        return None;
+    }
+    Some(HeaderValue::from_str(&codings.join(", ")).expect("codings are valid header"))
+}
+
+fn is_supported(coding: &str) -> bool {
+    (cfg!(feature = "gzip") && (coding == "gzip" || coding == "x-gzip"))
+        || (cfg!(feature = "deflate") && coding == "deflate")
+        || (cfg!(feature = "brotli") && coding == "br")
+}
+
+/// Returns the content codings in the order they were applied.
+///
+/// The `identity` coding is skipped. Returns `None` if a
+/// header value is not valid.
+fn content_codings(headers: &HeaderMap) -> Option<Vec<String>> {
+    let mut codings = Vec::new();
+    for value in headers.get_all(header::CONTENT_ENCODING) {
+        for coding in value.to_str().ok()?.split(',') {
+            let coding = coding.trim().to_ascii_lowercase();
+            if !coding.is_empty() && coding != "identity" {
+                codings.push(coding);
+            }
+        }
+    }
+    Some(codings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "gzip", feature = "deflate"))]
+    use std::io::Write;
+
+    #[cfg(feature = "deflate")]
+    use flate2::write::ZlibEncoder;
+    #[cfg(feature = "gzip")]
+    use flate2::write::GzEncoder;
+    #[cfg(any(feature = "gzip", feature = "deflate"))]
+    use flate2::Compression;
+    use http::Response;
+
+    fn parts(content_encoding: &str, content_length: usize) -> Parts {
+        Response::builder()
+            .header(header::CONTENT_ENCODING, content_encoding)
+            .header(header::CONTENT_LENGTH, content_length.to_string().as_str())
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0
+    }
+
+    fn decode(body: &[u8], content_encoding: &str) -> (io::Result<Vec<u8>>, Parts) {
+        let mut parts = parts(content_encoding, body.len());
+        let mut decoder = Decoder::from_response(body, &mut parts);
+        let mut decoded = Vec::new();
+        let result = decoder.read_to_end(&mut decoded).map(|_| decoded);
+        (result, parts)
+    }
+
+    #[cfg(feature = "gzip")]
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[cfg(feature = "deflate")]
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decodes_empty_bodies() {
+        for coding in &["gzip", "deflate", "br", "gzip, deflate"] {
+            let (result, parts) = decode(b"", coding);
+            assert_eq!(result.unwrap(), b"", "{}", coding);
+            let removed = coding.split(", ").all(is_supported);
+            assert_eq!(!parts.headers.contains_key(header::CONTENT_ENCODING), removed);
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn rejects_truncated_bodies() {
+        let body = gzip(b"hello");
+        let (result, _) = decode(&body[..body.len() / 2], "gzip");
+        assert!(result.is_err());
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn decodes_deflate() {
+        let (result, parts) = decode(&deflate(b"hello world"), "deflate");
+        assert_eq!(result.unwrap(), b"hello world");
+        assert!(!parts.headers.contains_key(header::CONTENT_ENCODING));
+    }
+
+    #[cfg(all(feature = "gzip", feature = "deflate"))]
+    #[test]
+    fn decodes_stacked_codings() {
+        let body = gzip(&deflate(b"hello world"));
+        let (result, parts) = decode(&body, "deflate, GZIP");
+        assert_eq!(result.unwrap(), b"hello world");
+        assert!(!parts.headers.contains_key(header::CONTENT_ENCODING));
+        assert!(!parts.headers.contains_key(header::CONTENT_LENGTH));
+
+        let body = gzip(b"hello world");
+        let (result, _) = decode(&body, "identity, gzip");
+        assert_eq!(result.unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn keeps_unsupported_codings() {
+        let (result, parts) = decode(b"\x1f\x9d", "gzip, compress");
+        assert_eq!(result.unwrap(), b"\x1f\x9d");
+        assert_eq!(parts.headers[header::CONTENT_ENCODING], "gzip, compress");
+        assert_eq!(parts.headers[header::CONTENT_LENGTH], "2");
+    }
+
+    #[test]
+    fn lists_codings() {
+        let mut headers = HeaderMap::new();
+        headers.append(header::CONTENT_ENCODING, HeaderValue::from_static("Gzip, identity,"));
+        headers.append(header::CONTENT_ENCODING, HeaderValue::from_static(" br"));
+        assert_eq!(content_codings(&headers).unwrap(), vec!["gzip", "br"]);
+        headers.append(header::CONTENT_ENCODING, HeaderValue::from_bytes(b"\xff").unwrap());
+        assert_eq!(content_codings(&headers), None);
+    }
+}
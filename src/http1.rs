@@ -1,28 +1,87 @@
 use std::io::{self, BufRead, Write};
 
 use http::{header, request, response, Request, Version};
+use http::header::HeaderMap;
 use httparse;
 
 use util::wrap_error;
 use Error;
 
-pub fn write_request_header<T, W: Write>(writer: &mut W, req: &Request<T>) -> io::Result<()> {
+/// Writes the request line and header fields.
+///
+/// Fields in `extra` are added by the client for this connection only,
+/// they replace fields of the same name in the request.
+/// Requests sent to a proxy use the absolute URL as request target.
+pub fn write_request_header<T, W: Write>(
+    writer: &mut W,
+    req: &Request<T>,
+    extra: &HeaderMap,
+    absolute_form: bool,
+) -> io::Result<()> {
     let (_, authority, path) = wrap_error(request::get_target_components(req))?;
     let version_str = match req.version() {
         Version::HTTP_10 => "HTTP/1.0",
         Version::HTTP_11 => "HTTP/1.1",
         v => panic!("Unsupported version: {:?}", v),
     };
-    write!(writer, "{} {} {}", req.method(), path, version_str)?;
+    if absolute_form {
+        let scheme = req.url().scheme();
+        write!(writer, "{} {}://{}{} {}", req.method(), scheme, authority, path, version_str)?;
+    } else {
+        write!(writer, "{} {} {}", req.method(), path, version_str)?;
+    }
     write!(writer, "\r\n{}: {}", header::HOST.as_str(), authority)?;
     for (name, value) in req.headers().iter() {
+        if extra.contains_key(name) {
+            continue;
+        }
         write!(writer, "\r\n{}: ", name.as_str())?;
-        writer.write(value.as_bytes())?;
+        writer.write_all(value.as_bytes())?;
+    }
+    for (name, value) in extra.iter() {
+        write!(writer, "\r\n{}: ", name.as_str())?;
+        writer.write_all(value.as_bytes())?;
     }
     write!(writer, "\r\n\r\n")?;
     Ok(())
 }
 
+/// Writes data with chunked transfer encoding.
+///
+/// Each write becomes a single chunk. Call `finish` to
+/// write the last chunk which terminates the body.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> ChunkedWriter<W> {
+        ChunkedWriter { inner }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would terminate the body.
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.inner, "{:X}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 pub fn read_response_header<R: BufRead>(reader: &mut R) -> io::Result<response::Parts> {
     loop {
         let len;
@@ -31,6 +90,12 @@ pub fn read_response_header<R: BufRead>(reader: &mut R) -> io::Result<response::
             let mut headers = [httparse::EMPTY_HEADER; 64];
             let mut resp = httparse::Response::new(&mut headers);
             let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed before response",
+                ));
+            }
             let parse_state = wrap_error(resp.parse(buf))?;
             if parse_state.is_partial() {
                 continue;
@@ -1,13 +1,50 @@
+// The suggested replacements need a newer compiler than the dependencies.
+#![allow(clippy::io_other_error, clippy::unnecessary_map_or)]
+
+#[cfg(feature = "brotli")]
+extern crate brotli_decompressor;
+extern crate base64;
+extern crate encoding_rs;
+#[cfg(any(feature = "gzip", feature = "deflate"))]
+extern crate flate2;
 extern crate http_with_url as http;
 extern crate httparse;
 #[macro_use]
 extern crate log;
+extern crate md5;
+#[cfg(feature = "native-tls")]
 extern crate native_tls;
+#[cfg(feature = "rustls-tls")]
+extern crate rustls;
+#[cfg(feature = "json")]
+#[macro_use]
+extern crate serde_json;
+extern crate sha2;
+#[cfg(feature = "rustls-tls")]
+extern crate webpki_roots;
 
-pub use body::{Body, FromBody};
-pub use client::{Client, Error};
+pub use auth::Credentials;
+pub use aws::AwsSigner;
+pub use body::{Body, FromBody, StreamBody, ToBody};
+pub use client::{Client, ClientBuilder, Error};
+pub use cookie::{Cookie, CookieJar, SameSite};
+pub use netrc::Netrc;
+pub use pool::{IdleConnection, PooledConnection};
+pub use proxy::Proxy;
+pub use redirect::{Action, Attempt, RedirectHistory, RedirectPolicy};
+pub use tls::{Certificate, Identity, TlsBackend, TlsInfo, TlsVersion};
 
+mod auth;
+mod aws;
 mod body;
 mod client;
+mod cookie;
+mod decode;
 mod http1;
+mod netrc;
+mod pool;
+mod proxy;
+mod redirect;
+mod socks;
+mod tls;
 mod util;
@@ -0,0 +1,180 @@
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+
+/// Credentials read from a `.netrc` file.
+///
+/// A client configured with `ClientBuilder::netrc` uses the credentials
+/// for a host only after the server responds with `401 Unauthorized`
+/// and offers basic authentication.
+///
+/// Entries are introduced by `machine` with a host name or by `default`
+/// which matches all hosts. `login` and `password` set the credentials,
+/// `account` is ignored and macros defined with `macdef` are skipped.
+#[derive(Clone, Debug, Default)]
+pub struct Netrc {
+    entries: Vec<Entry>,
+}
+
+#[derive(Clone, Default)]
+struct Entry {
+    /// The host name or `None` for the default entry.
+    machine: Option<String>,
+    login: String,
+    password: String,
+}
+
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print secrets.
+        f.debug_struct("Entry")
+            .field("machine", &self.machine)
+            .field("login", &self.login)
+            .finish()
+    }
+}
+
+impl Netrc {
+    /// Reads the file named by the `NETRC` environment variable
+    /// or `.netrc` in the home directory.
+    pub fn from_home() -> io::Result<Netrc> {
+        let path = if let Some(path) = env::var_os("NETRC") {
+            PathBuf::from(path)
+        } else if let Some(home) = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")) {
+            PathBuf::from(home).join(".netrc")
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "home directory not found",
+            ));
+        };
+        Netrc::parse(BufReader::new(File::open(path)?))
+    }
+
+    /// Parses a netrc file.
+    ///
+    /// Unknown tokens are ignored.
+    pub fn parse<R: BufRead>(reader: R) -> io::Result<Netrc> {
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut key: Option<String> = None;
+        let mut in_macdef = false;
+        for line in reader.lines() {
+            let line = line?;
+            if in_macdef {
+                // A macro ends with an empty line.
+                in_macdef = !line.trim().is_empty();
+                continue;
+            }
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+            for token in line.split_whitespace() {
+                if let Some(key) = key.take() {
+                    match &key[..] {
+                        "machine" => entries.push(Entry {
+                            machine: Some(token.to_ascii_lowercase()),
+                            ..Entry::default()
+                        }),
+                        "login" => if let Some(entry) = entries.last_mut() {
+                            entry.login = token.to_string();
+                        },
+                        "password" => if let Some(entry) = entries.last_mut() {
+                            entry.password = token.to_string();
+                        },
+                        _ => {}
+                    }
+                    continue;
+                }
+                match token {
+                    "machine" | "login" | "password" | "account" => key = Some(token.to_string()),
+                    "default" => entries.push(Entry::default()),
+                    "macdef" => {
+                        // The rest of the line is the macro name.
+                        in_macdef = true;
+                        break;
+                    }
+                    _ => debug!("Ignoring unknown netrc token {:?}", token),
+                }
+            }
+        }
+        Ok(Netrc { entries })
+    }
+
+    /// Returns the login and password for `host`.
+    ///
+    /// The first matching `machine` entry is used,
+    /// otherwise the `default` entry if present.
+    pub fn credentials(&self, host: &str) -> Option<(&str, &str)> {
+        let host = host.to_ascii_lowercase();
+        self.entries
+            .iter()
+            .find(|entry| entry.machine.as_ref() == Some(&host))
+            .or_else(|| self.entries.iter().find(|entry| entry.machine.is_none()))
+            .map(|entry| (&entry.login[..], &entry.password[..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Netrc {
+        Netrc::parse(input.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn finds_machines() {
+        let netrc = parse(
+            "machine example.com login alice password secret\n\
+             machine Example.org\n  login bob\n  account ignored\n  password hunter2\n\
+             machine example.com login mallory password other\n",
+        );
+        assert_eq!(netrc.credentials("example.com"), Some(("alice", "secret")));
+        assert_eq!(netrc.credentials("EXAMPLE.ORG"), Some(("bob", "hunter2")));
+        assert_eq!(netrc.credentials("example.net"), None);
+    }
+
+    #[test]
+    fn hides_passwords_in_debug_output() {
+        let netrc = parse("machine example.com login alice password secret\n");
+        let debug = format!("{:?}", netrc);
+        assert!(debug.contains("alice"));
+        assert!(!debug.contains("secret"));
+    }
+
+    #[test]
+    fn falls_back_to_default() {
+        let netrc = parse(
+            "default login anonymous password guest\n\
+             machine example.com login alice password secret\n",
+        );
+        assert_eq!(netrc.credentials("example.com"), Some(("alice", "secret")));
+        assert_eq!(netrc.credentials("example.net"), Some(("anonymous", "guest")));
+    }
+
+    #[test]
+    fn skips_macros_and_comments() {
+        let netrc = parse(
+            "# machine commented.com login x password y\n\
+             machine example.com login alice\n\
+             macdef init\n\
+             machine macro.com login eve password evil\n\
+             cd /pub\n\
+             \n\
+             password secret\n\
+             machine next.com login bob password hunter2 unknown token\n",
+        );
+        assert_eq!(netrc.credentials("commented.com"), None);
+        assert_eq!(netrc.credentials("macro.com"), None);
+        assert_eq!(netrc.credentials("example.com"), Some(("alice", "secret")));
+        assert_eq!(netrc.credentials("next.com"), Some(("bob", "hunter2")));
+    }
+
+    #[test]
+    fn ignores_credentials_before_first_machine() {
+        let netrc = parse("login alice password secret\n");
+        assert_eq!(netrc.credentials("example.com"), None);
+    }
+}
@@ -0,0 +1,251 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+use http::{StatusCode, Url};
+
+use Error;
+
+/// Decides which redirects a client follows.
+///
+/// The default policy follows up to 20 redirects.
+///
+/// ```rust
+/// # extern crate boguin;
+/// # fn main() {
+/// use boguin::RedirectPolicy;
+///
+/// // Follow redirects within the same host but never downgrade to HTTP.
+/// let policy = RedirectPolicy::custom(|attempt| {
+///     if attempt.history().len() > 3 {
+///         attempt.stop()
+///     } else if attempt.previous().scheme() == "https" && attempt.url().scheme() == "http" {
+///         attempt.error("redirect from HTTPS to HTTP")
+///     } else if attempt.previous().host_str() != attempt.url().host_str() {
+///         attempt.stop()
+///     } else {
+///         attempt.follow()
+///     }
+/// });
+/// # let _ = policy;
+/// # }
+/// ```
+pub struct RedirectPolicy {
+    inner: Policy,
+}
+
+enum Policy {
+    None,
+    Limited(usize),
+    Custom(Box<dyn Fn(Attempt) -> Action + Send + Sync>),
+}
+
+impl RedirectPolicy {
+    /// Never follow redirects.
+    ///
+    /// The redirect response itself is returned.
+    pub fn none() -> RedirectPolicy {
+        RedirectPolicy {
+            inner: Policy::None,
+        }
+    }
+
+    /// Follow up to `max` redirects.
+    ///
+    /// If a response would exceed the limit the request fails
+    /// with `Error::TooManyRedirects`.
+    pub fn limited(max: usize) -> RedirectPolicy {
+        RedirectPolicy {
+            inner: Policy::Limited(max),
+        }
+    }
+
+    /// Decide for each redirect with a custom function.
+    ///
+    /// The function is called before each redirect is followed
+    /// and returns the action to take.
+    pub fn custom<F>(policy: F) -> RedirectPolicy
+    where
+        F: Fn(Attempt) -> Action + Send + Sync + 'static,
+    {
+        RedirectPolicy {
+            inner: Policy::Custom(Box::new(policy)),
+        }
+    }
+
+    pub(crate) fn check(&self, attempt: Attempt) -> io::Result<bool> {
+        let action = match self.inner {
+            Policy::None => attempt.stop(),
+            Policy::Limited(max) => {
+                if attempt.history.len() > max {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        Error::TooManyRedirects,
+                    ));
+                }
+                attempt.follow()
+            }
+            Policy::Custom(ref policy) => policy(attempt),
+        };
+        match action.inner {
+            ActionKind::Follow => Ok(true),
+            ActionKind::Stop => Ok(false),
+            ActionKind::Error(err) => Err(io::Error::new(io::ErrorKind::Other, err)),
+        }
+    }
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> RedirectPolicy {
+        RedirectPolicy::limited(20)
+    }
+}
+
+impl fmt::Debug for RedirectPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.inner {
+            Policy::None => f.write_str("None"),
+            Policy::Limited(max) => f.debug_tuple("Limited").field(&max).finish(),
+            Policy::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// The redirects followed to receive a response.
+///
+/// Every response returned by a `Client` contains the history in its
+/// extensions:
+///
+/// ```rust,no_run
+/// # extern crate boguin;
+/// # extern crate http_with_url as http;
+/// # fn main() {
+/// use boguin::RedirectHistory;
+///
+/// let client = boguin::Client::new();
+/// let url = http::Url::parse("https://httpbin.org/redirect/2").unwrap();
+/// let response: http::Response<()> = client.fetch(http::Request::new(url, ())).unwrap();
+/// let history = response.extensions().get::<RedirectHistory>().unwrap();
+/// for &(ref url, status) in history.hops() {
+///     println!("{} redirected with {}", url, status);
+/// }
+/// println!("final URL: {}", history.url());
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct RedirectHistory {
+    hops: Vec<(Url, StatusCode)>,
+    url: Url,
+}
+
+impl RedirectHistory {
+    /// Creates the history from the URLs of all requests sent
+    /// and the status codes of all redirects followed.
+    pub(crate) fn new(mut urls: Vec<Url>, statuses: Vec<StatusCode>) -> RedirectHistory {
+        let url = urls.pop().expect("at least one request was sent");
+        debug_assert_eq!(urls.len(), statuses.len());
+        RedirectHistory {
+            hops: urls.into_iter().zip(statuses).collect(),
+            url,
+        }
+    }
+
+    /// Returns the redirects followed, oldest first.
+    ///
+    /// Each hop is the URL of a request and the
+    /// status code of the redirect response to it.
+    pub fn hops(&self) -> &[(Url, StatusCode)] {
+        &self.hops
+    }
+
+    /// Returns the effective URL of the response.
+    ///
+    /// This is the URL of the last request sent. It differs from the
+    /// URL of the original request if a redirect was followed.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+}
+
+/// A redirect the client is about to follow.
+#[derive(Debug)]
+pub struct Attempt<'a> {
+    status: StatusCode,
+    next: &'a Url,
+    history: &'a [Url],
+}
+
+impl<'a> Attempt<'a> {
+    pub(crate) fn new(status: StatusCode, next: &'a Url, history: &'a [Url]) -> Attempt<'a> {
+        debug_assert!(!history.is_empty());
+        Attempt {
+            status,
+            next,
+            history,
+        }
+    }
+
+    /// Returns the status code of the redirect response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns the URL the redirect points to.
+    pub fn url(&self) -> &Url {
+        self.next
+    }
+
+    /// Returns the URL of the request that was redirected.
+    pub fn previous(&self) -> &Url {
+        self.history.last().expect("history contains the previous URL")
+    }
+
+    /// Returns the URLs of all requests sent so far, oldest first.
+    ///
+    /// The first entry is the URL of the original request and
+    /// the last entry is the URL returned by `previous`.
+    pub fn history(&self) -> &[Url] {
+        self.history
+    }
+
+    /// Follow the redirect.
+    pub fn follow(self) -> Action {
+        Action {
+            inner: ActionKind::Follow,
+        }
+    }
+
+    /// Do not follow the redirect and return the redirect response.
+    pub fn stop(self) -> Action {
+        Action {
+            inner: ActionKind::Stop,
+        }
+    }
+
+    /// Fail the request with the given error.
+    ///
+    /// The error is wrapped in an `io::Error` of kind `Other`.
+    pub fn error<E>(self, error: E) -> Action
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        Action {
+            inner: ActionKind::Error(error.into()),
+        }
+    }
+}
+
+/// The decision of a `RedirectPolicy` for a redirect.
+///
+/// Created by the methods of `Attempt`.
+#[derive(Debug)]
+pub struct Action {
+    inner: ActionKind,
+}
+
+#[derive(Debug)]
+enum ActionKind {
+    Follow,
+    Stop,
+    Error(Box<dyn StdError + Send + Sync>),
+}
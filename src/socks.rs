@@ -0,0 +1,385 @@
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+
+use http::url::Host;
+
+use Error;
+
+/// Asks a SOCKS5 proxy to connect to `host` and `port`.
+///
+/// With `remote_dns` the proxy resolves host names,
+/// otherwise they are resolved locally.
+// https://tools.ietf.org/html/rfc1928
+pub(crate) fn connect_socks5(
+    mut stream: TcpStream,
+    host: Host<&str>,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+    remote_dns: bool,
+) -> io::Result<TcpStream> {
+    if credentials.is_some() {
+        // Offer no authentication and username/password.
+        stream.write_all(&[5, 2, 0, 2])?;
+    } else {
+        stream.write_all(&[5, 1, 0])?;
+    }
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != 5 {
+        return Err(bad_response());
+    }
+    match (reply[1], credentials) {
+        (0, _) => {}
+        (2, Some((username, password))) => authenticate(&mut stream, username, password)?,
+        _ => {
+            warn!("SOCKS5 proxy accepts none of the offered authentication methods");
+            return Err(refused());
+        }
+    }
+
+    let mut request = vec![5, 1, 0];
+    match host {
+        Host::Domain(domain) if remote_dns => {
+            request.push(3);
+            request.push(short_len(domain.as_bytes())?);
+            request.extend_from_slice(domain.as_bytes());
+        }
+        Host::Domain(domain) => push_ip(&mut request, resolve(domain, port)?),
+        Host::Ipv4(ip) => push_ip(&mut request, IpAddr::V4(ip)),
+        Host::Ipv6(ip) => push_ip(&mut request, IpAddr::V6(ip)),
+    }
+    request.push((port >> 8) as u8);
+    request.push(port as u8);
+    stream.write_all(&request)?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != 5 {
+        return Err(bad_response());
+    }
+    if reply[1] != 0 {
+        warn!("SOCKS5 proxy refused connection with reply code {}", reply[1]);
+        return Err(refused());
+    }
+    // Skip the address the proxy bound and its port.
+    let addr_len = match reply[3] {
+        1 => 4,
+        3 => {
+            let mut len = [0; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        4 => 16,
+        _ => return Err(bad_response()),
+    };
+    let mut bound = vec![0; addr_len + 2];
+    stream.read_exact(&mut bound)?;
+    Ok(stream)
+}
+
+/// Asks a SOCKS4a proxy to connect to `host` and `port`.
+///
+/// SOCKS4a lets the proxy resolve host names
+/// but supports no IPv6 addresses.
+pub(crate) fn connect_socks4a(
+    mut stream: TcpStream,
+    host: Host<&str>,
+    port: u16,
+    user_id: &str,
+) -> io::Result<TcpStream> {
+    let mut request = vec![4, 1, (port >> 8) as u8, port as u8];
+    match host {
+        Host::Domain(domain) => {
+            // An invalid IP address tells the proxy a domain follows.
+            request.extend_from_slice(&[0, 0, 0, 1]);
+            request.extend_from_slice(user_id.as_bytes());
+            request.push(0);
+            request.extend_from_slice(domain.as_bytes());
+            request.push(0);
+        }
+        Host::Ipv4(ip) => {
+            request.extend_from_slice(&ip.octets());
+            request.extend_from_slice(user_id.as_bytes());
+            request.push(0);
+        }
+        Host::Ipv6(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SOCKS4 does not support IPv6 addresses",
+            ))
+        }
+    }
+    stream.write_all(&request)?;
+
+    let mut reply = [0; 8];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != 0 {
+        return Err(bad_response());
+    }
+    if reply[1] != 0x5a {
+        warn!("SOCKS4 proxy refused connection with reply code {}", reply[1]);
+        return Err(refused());
+    }
+    Ok(stream)
+}
+
+// https://tools.ietf.org/html/rfc1929
+fn authenticate(stream: &mut TcpStream, username: &str, password: &str) -> io::Result<()> {
+    let mut request = vec![1];
+    request.push(short_len(username.as_bytes())?);
+    request.extend_from_slice(username.as_bytes());
+    request.push(short_len(password.as_bytes())?);
+    request.extend_from_slice(password.as_bytes());
+    stream.write_all(&request)?;
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != 1 {
+        return Err(bad_response());
+    }
+    if reply[1] != 0 {
+        warn!("SOCKS5 proxy rejected username and password");
+        return Err(refused());
+    }
+    Ok(())
+}
+
+fn resolve(domain: &str, port: u16) -> io::Result<IpAddr> {
+    if let Some(addr) = (domain, port).to_socket_addrs()?.next() {
+        Ok(addr.ip())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any address",
+        ))
+    }
+}
+
+fn push_ip(request: &mut Vec<u8>, ip: IpAddr) {
+    match ip {
+        IpAddr::V4(ip) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        }
+    }
+}
+
+/// Returns the length of a field prefixed with a single byte.
+fn short_len(field: &[u8]) -> io::Result<u8> {
+    if field.len() > 255 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "SOCKS field is longer than 255 bytes",
+        ));
+    }
+    Ok(field.len() as u8)
+}
+
+fn bad_response() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, Error::BadResponse)
+}
+
+fn refused() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, Error::ProxyTunnel)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr, TcpListener};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Connects to a proxy that expects each request and sends its reply.
+    fn proxy(exchanges: Vec<(Vec<u8>, Vec<u8>)>) -> (TcpStream, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            for (request, reply) in exchanges {
+                let mut received = vec![0; request.len()];
+                stream.read_exact(&mut received).unwrap();
+                assert_eq!(received, request);
+                stream.write_all(&reply).unwrap();
+            }
+        });
+        (stream, handle)
+    }
+
+    fn test_ip() -> Host<&'static str> {
+        Host::Ipv4(Ipv4Addr::new(192, 0, 2, 1))
+    }
+
+    fn bytes(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    #[test]
+    fn socks5_connects_to_ip_addresses() {
+        let (stream, handle) = proxy(vec![
+            (vec![5, 1, 0], vec![5, 0]),
+            (
+                vec![5, 1, 0, 1, 192, 0, 2, 1, 0, 80],
+                bytes(&[&[5, 0, 0, 1, 10, 0, 0, 1, 4, 56], b"data"]),
+            ),
+        ]);
+        let mut stream = connect_socks5(stream, test_ip(), 80, None, false).unwrap();
+        let mut data = [0; 4];
+        stream.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"data");
+        handle.join().unwrap();
+
+        let ip = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let (stream, handle) = proxy(vec![
+            (vec![5, 1, 0], vec![5, 0]),
+            (
+                bytes(&[&[5, 1, 0, 4], &ip.octets(), &[1, 187]]),
+                bytes(&[&[5, 0, 0, 4], &ip.octets(), &[4, 56], b"data"]),
+            ),
+        ]);
+        let mut stream = connect_socks5(stream, Host::Ipv6(ip), 443, None, false).unwrap();
+        stream.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"data");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn socks5_authenticates_and_resolves_remotely() {
+        let (stream, handle) = proxy(vec![
+            (vec![5, 2, 0, 2], vec![5, 2]),
+            (bytes(&[&[1, 4], b"user", &[6], b"secret"]), vec![1, 0]),
+            (
+                bytes(&[&[5, 1, 0, 3, 11], b"example.com", &[1, 187]]),
+                bytes(&[&[5, 0, 0, 3, 5], b"proxy", &[4, 56], b"data"]),
+            ),
+        ]);
+        let host = Host::Domain("example.com");
+        let credentials = Some(("user", "secret"));
+        let mut stream = connect_socks5(stream, host, 443, credentials, true).unwrap();
+        let mut data = [0; 4];
+        stream.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"data");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn socks5_skips_authentication_if_not_required() {
+        let (stream, handle) = proxy(vec![
+            (vec![5, 2, 0, 2], vec![5, 0]),
+            (
+                bytes(&[&[5, 1, 0, 3, 11], b"example.com", &[0, 80]]),
+                vec![5, 0, 0, 1, 0, 0, 0, 0, 0, 0],
+            ),
+        ]);
+        let host = Host::Domain("example.com");
+        connect_socks5(stream, host, 80, Some(("user", "secret")), true).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn socks5_rejections() {
+        // No acceptable authentication method.
+        let (stream, handle) = proxy(vec![(vec![5, 1, 0], vec![5, 0xff])]);
+        let err = connect_socks5(stream, test_ip(), 80, None, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        handle.join().unwrap();
+
+        // Username/password is required but none were given.
+        let (stream, handle) = proxy(vec![(vec![5, 1, 0], vec![5, 2])]);
+        let err = connect_socks5(stream, test_ip(), 80, None, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        handle.join().unwrap();
+
+        // Wrong username or password.
+        let (stream, handle) = proxy(vec![
+            (vec![5, 2, 0, 2], vec![5, 2]),
+            (bytes(&[&[1, 1], b"u", &[1], b"p"]), vec![1, 1]),
+        ]);
+        let err = connect_socks5(stream, test_ip(), 80, Some(("u", "p")), false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        handle.join().unwrap();
+
+        // Each failure reply code of RFC 1928.
+        for code in 1..9 {
+            let (stream, handle) = proxy(vec![
+                (vec![5, 1, 0], vec![5, 0]),
+                (
+                    vec![5, 1, 0, 1, 192, 0, 2, 1, 0, 80],
+                    vec![5, code, 0, 1, 0, 0, 0, 0, 0, 0],
+                ),
+            ]);
+            let err = connect_socks5(stream, test_ip(), 80, None, false).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Other, "reply code {}", code);
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn socks5_rejects_bad_responses() {
+        let (stream, handle) = proxy(vec![(vec![5, 1, 0], vec![4, 0])]);
+        let err = connect_socks5(stream, test_ip(), 80, None, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        handle.join().unwrap();
+
+        // Unknown address type in the reply.
+        let (stream, handle) = proxy(vec![
+            (vec![5, 1, 0], vec![5, 0]),
+            (vec![5, 1, 0, 1, 192, 0, 2, 1, 0, 80], vec![5, 0, 0, 2]),
+        ]);
+        let err = connect_socks5(stream, test_ip(), 80, None, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn socks4a_connects() {
+        let (stream, handle) = proxy(vec![(
+            bytes(&[&[4, 1, 1, 187, 0, 0, 0, 1], b"user", &[0], b"example.com", &[0]]),
+            bytes(&[&[0, 0x5a, 0, 0, 0, 0, 0, 0], b"data"]),
+        )]);
+        let mut stream = connect_socks4a(stream, Host::Domain("example.com"), 443, "user").unwrap();
+        let mut data = [0; 4];
+        stream.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"data");
+        handle.join().unwrap();
+
+        let (stream, handle) = proxy(vec![(
+            vec![4, 1, 0, 80, 192, 0, 2, 1, 0],
+            vec![0, 0x5a, 0, 0, 0, 0, 0, 0],
+        )]);
+        connect_socks4a(stream, test_ip(), 80, "").unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn socks4a_rejections() {
+        for &code in &[0x5b, 0x5c, 0x5d] {
+            let (stream, handle) = proxy(vec![(
+                vec![4, 1, 0, 80, 192, 0, 2, 1, 0],
+                vec![0, code, 0, 0, 0, 0, 0, 0],
+            )]);
+            let err = connect_socks4a(stream, test_ip(), 80, "").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Other, "reply code {}", code);
+            handle.join().unwrap();
+        }
+
+        let (stream, handle) = proxy(vec![(
+            vec![4, 1, 0, 80, 192, 0, 2, 1, 0],
+            vec![4, 0x5a, 0, 0, 0, 0, 0, 0],
+        )]);
+        let err = connect_socks4a(stream, test_ip(), 80, "").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        handle.join().unwrap();
+
+        let (stream, handle) = proxy(Vec::new());
+        let err = connect_socks4a(stream, Host::Ipv6(Ipv6Addr::LOCALHOST), 80, "").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,399 @@
+// Certificates and identities are only read by the backends.
+#![cfg_attr(not(any(feature = "native-tls", feature = "rustls-tls")), allow(dead_code))]
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::str;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use base64;
+use sha2::{Digest, Sha256};
+
+use util::wrap_error;
+
+#[cfg(feature = "native-tls")]
+mod native;
+#[cfg(feature = "rustls-tls")]
+mod rustls;
+mod x509;
+
+/// A TLS connection to a server.
+pub(crate) trait TlsStream: Read + Write + Send {
+    /// Returns the underlying TCP connection.
+    fn tcp(&self) -> &TcpStream;
+
+    /// Returns the DER encoded certificates presented by the server, leaf first.
+    fn peer_certificates(&self) -> Vec<Vec<u8>>;
+
+    /// Returns the negotiated protocol version if known.
+    fn version(&self) -> Option<TlsVersion>;
+
+    /// Returns the name of the negotiated cipher suite if known.
+    fn cipher_suite(&self) -> Option<String>;
+
+    /// Returns the protocol selected with ALPN.
+    fn alpn_protocol(&self) -> Option<Vec<u8>>;
+}
+
+/// Opens TLS connections with a TLS implementation.
+pub(crate) trait TlsConnector: Send + Sync {
+    /// Runs the TLS handshake on `stream` and verifies the server is `name`.
+    ///
+    /// `name` is a domain or an IP address.
+    fn connect(&self, name: &str, stream: TcpStream) -> io::Result<Box<dyn TlsStream>>;
+}
+
+/// The TLS implementation used by a client.
+///
+/// Each implementation is enabled by a cargo feature. If both are enabled
+/// `NativeTls` is the default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum TlsBackend {
+    /// The TLS library of the operating system, OpenSSL on Linux.
+    ///
+    /// Enabled by the default feature `native-tls`.
+    #[cfg(feature = "native-tls")]
+    NativeTls,
+    /// The pure Rust implementation rustls with the Mozilla root certificates.
+    ///
+    /// Enabled by the feature `rustls-tls`. TLS 1.0, TLS 1.1
+    /// and PKCS #12 identities are not supported.
+    #[cfg(feature = "rustls-tls")]
+    Rustls,
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+#[cfg(feature = "native-tls")]
+const DEFAULT_BACKEND: TlsBackend = TlsBackend::NativeTls;
+#[cfg(all(feature = "rustls-tls", not(feature = "native-tls")))]
+const DEFAULT_BACKEND: TlsBackend = TlsBackend::Rustls;
+#[cfg(not(any(feature = "native-tls", feature = "rustls-tls")))]
+const DEFAULT_BACKEND: TlsBackend = TlsBackend::__Nonexhaustive;
+
+impl Default for TlsBackend {
+    fn default() -> TlsBackend {
+        DEFAULT_BACKEND
+    }
+}
+
+/// A certificate trusted as root of certificate chains.
+#[derive(Clone)]
+pub struct Certificate {
+    der: Vec<u8>,
+}
+
+impl Certificate {
+    /// Parses a PEM encoded X.509 certificate.
+    ///
+    /// Fails if the input contains more than one certificate,
+    /// use `from_pem_bundle` to read all of them.
+    pub fn from_pem(pem: &[u8]) -> io::Result<Certificate> {
+        let mut certificates = Certificate::from_pem_bundle(pem)?;
+        if certificates.len() > 1 {
+            return Err(invalid_pem("more than one certificate found"));
+        }
+        Ok(certificates.remove(0))
+    }
+
+    /// Parses a bundle of PEM encoded X.509 certificates.
+    ///
+    /// Fails if the input contains no certificate.
+    pub fn from_pem_bundle(pem: &[u8]) -> io::Result<Vec<Certificate>> {
+        let certificates: Vec<Certificate> = pem_blocks(pem)?
+            .into_iter()
+            .filter(|(label, _)| label == "CERTIFICATE")
+            .map(|(_, der)| Certificate { der })
+            .collect();
+        if certificates.is_empty() {
+            return Err(invalid_pem("no certificate found"));
+        }
+        Ok(certificates)
+    }
+
+    /// Reads a DER encoded X.509 certificate.
+    ///
+    /// The certificate is parsed by the TLS backend
+    /// when the first connection is opened.
+    pub fn from_der(der: &[u8]) -> io::Result<Certificate> {
+        if der.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "certificate is empty",
+            ));
+        }
+        Ok(Certificate { der: der.to_vec() })
+    }
+
+    pub(crate) fn der(&self) -> &[u8] {
+        &self.der
+    }
+}
+
+impl fmt::Debug for Certificate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Certificate")
+    }
+}
+
+/// A client certificate chain and private key for mutual TLS.
+#[derive(Clone)]
+pub struct Identity {
+    inner: IdentityKind,
+}
+
+#[derive(Clone)]
+enum IdentityKind {
+    // Only supported by native-tls.
+    #[cfg_attr(not(feature = "native-tls"), allow(dead_code))]
+    Pkcs12 { der: Vec<u8>, password: String },
+    Pem { certificates: Vec<u8>, key: Vec<u8> },
+}
+
+impl Identity {
+    /// Reads a DER encoded PKCS #12 archive protected by `password`.
+    ///
+    /// The archive is parsed by the TLS backend
+    /// when the first connection is opened.
+    pub fn from_pkcs12(der: &[u8], password: &str) -> io::Result<Identity> {
+        Ok(Identity {
+            inner: IdentityKind::Pkcs12 {
+                der: der.to_vec(),
+                password: password.to_string(),
+            },
+        })
+    }
+
+    /// Parses a chain of PEM encoded certificates, leaf first,
+    /// and a PEM encoded PKCS #8 private key.
+    pub fn from_pem(certificates: &[u8], key: &[u8]) -> io::Result<Identity> {
+        let identity = Identity {
+            inner: IdentityKind::Pem {
+                certificates: certificates.to_vec(),
+                key: key.to_vec(),
+            },
+        };
+        identity.pem_parts()?;
+        Ok(identity)
+    }
+
+    /// Returns the DER encoded certificates and private key of a PEM identity.
+    fn pem_parts(&self) -> io::Result<(Vec<Vec<u8>>, Vec<u8>)> {
+        let (certificates, key) = match self.inner {
+            IdentityKind::Pem {
+                ref certificates,
+                ref key,
+            } => (certificates, key),
+            IdentityKind::Pkcs12 { .. } => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "PKCS #12 identities are not supported",
+                ))
+            }
+        };
+        let certificates: Vec<Vec<u8>> = pem_blocks(certificates)?
+            .into_iter()
+            .filter(|(label, _)| label == "CERTIFICATE")
+            .map(|(_, der)| der)
+            .collect();
+        if certificates.is_empty() {
+            return Err(invalid_pem("no certificate found"));
+        }
+        let key = pem_blocks(key)?
+            .into_iter()
+            .find(|(label, _)| label.ends_with("PRIVATE KEY"))
+            .map(|(_, der)| der)
+            .ok_or_else(|| invalid_pem("no private key found"))?;
+        Ok((certificates, key))
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print the private key.
+        f.write_str("Identity")
+    }
+}
+
+/// A version of the TLS protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum TlsVersion {
+    /// TLS 1.0
+    Tls10,
+    /// TLS 1.1
+    Tls11,
+    /// TLS 1.2
+    Tls12,
+    /// TLS 1.3
+    ///
+    /// Not supported as minimum version by `TlsBackend::NativeTls`.
+    Tls13,
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// Details of the TLS session a response was received with.
+///
+/// Every response received over HTTPS contains the details in its
+/// extensions:
+///
+/// ```rust,no_run
+/// # extern crate boguin;
+/// # extern crate http_with_url as http;
+/// # fn main() {
+/// use boguin::TlsInfo;
+///
+/// let client = boguin::Client::new();
+/// let url = http::Url::parse("https://httpbin.org/get").unwrap();
+/// let response: http::Response<()> = client.fetch(http::Request::new(url, ())).unwrap();
+/// let info = response.extensions().get::<TlsInfo>().unwrap();
+/// println!("{:?} {:?}", info.version(), info.cipher_suite());
+/// println!("certificate expires {:?}", info.not_after());
+/// # }
+/// ```
+///
+/// `TlsBackend::NativeTls` does not report the version and cipher suite
+/// and only provides the leaf certificate.
+#[derive(Clone, Debug)]
+pub struct TlsInfo {
+    version: Option<TlsVersion>,
+    cipher_suite: Option<String>,
+    alpn_protocol: Option<Vec<u8>>,
+    peer_certificates: Vec<Vec<u8>>,
+}
+
+impl TlsInfo {
+    pub(crate) fn new(stream: &dyn TlsStream) -> TlsInfo {
+        TlsInfo {
+            version: stream.version(),
+            cipher_suite: stream.cipher_suite(),
+            alpn_protocol: stream.alpn_protocol(),
+            peer_certificates: stream.peer_certificates(),
+        }
+    }
+
+    /// Returns the negotiated protocol version.
+    ///
+    /// Always `None` with `TlsBackend::NativeTls`, which does not
+    /// expose the version.
+    pub fn version(&self) -> Option<TlsVersion> {
+        self.version
+    }
+
+    /// Returns the name of the cipher suite like `TLS13_AES_128_GCM_SHA256`.
+    ///
+    /// Always `None` with `TlsBackend::NativeTls`, which does not
+    /// expose the cipher suite.
+    pub fn cipher_suite(&self) -> Option<&str> {
+        self.cipher_suite.as_ref().map(|suite| &suite[..])
+    }
+
+    /// Returns the protocol selected with ALPN.
+    ///
+    /// See `ClientBuilder::alpn_protocols`.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_ref().map(|protocol| &protocol[..])
+    }
+
+    /// Returns the DER encoded certificates presented by the server, leaf first.
+    ///
+    /// Contains only the leaf certificate with `TlsBackend::NativeTls`.
+    pub fn peer_certificates(&self) -> &[Vec<u8>] {
+        &self.peer_certificates
+    }
+
+    /// Returns the DER encoded certificate of the server.
+    pub fn leaf_certificate(&self) -> Option<&[u8]> {
+        self.peer_certificates.first().map(|der| &der[..])
+    }
+
+    /// Returns the time the leaf certificate expires.
+    pub fn not_after(&self) -> Option<SystemTime> {
+        self.leaf_certificate().and_then(x509::not_after)
+    }
+}
+
+/// Settings for TLS connections of a client.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TlsConfig {
+    pub(crate) backend: TlsBackend,
+    pub(crate) roots: Vec<Certificate>,
+    pub(crate) identity: Option<Identity>,
+    pub(crate) min_version: Option<TlsVersion>,
+    pub(crate) alpn_protocols: Vec<String>,
+    pub(crate) accept_invalid_certs: bool,
+    /// Names used to verify hosts instead of the host name.
+    pub(crate) server_names: Vec<(String, String)>,
+    /// Base64 encoded SHA-256 hashes of public keys accepted for hosts.
+    pub(crate) pins: Vec<(String, Vec<String>)>,
+}
+
+impl TlsConfig {
+    pub(crate) fn server_name(&self, host: &str) -> Option<&str> {
+        self.server_names
+            .iter()
+            .find(|(h, _)| h == host)
+            .map(|(_, name)| &name[..])
+    }
+
+    pub(crate) fn pins(&self, host: &str) -> Option<&[String]> {
+        self.pins
+            .iter()
+            .find(|(h, _)| h == host)
+            .map(|(_, pins)| &pins[..])
+    }
+
+    pub(crate) fn connector(&self) -> io::Result<Arc<dyn TlsConnector>> {
+        if self.accept_invalid_certs {
+            warn!("Certificate and hostname verification is disabled");
+        }
+        match self.backend {
+            #[cfg(feature = "native-tls")]
+            TlsBackend::NativeTls => {
+                if !self.pins.is_empty() {
+                    warn!("Native TLS can only check pins against the leaf certificate");
+                }
+                Ok(Arc::new(native::NativeTlsConnector::new(self)?))
+            }
+            #[cfg(feature = "rustls-tls")]
+            TlsBackend::Rustls => Ok(Arc::new(rustls::RustlsConnector::new(self)?)),
+            TlsBackend::__Nonexhaustive => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "no TLS backend enabled",
+            )),
+        }
+    }
+}
+
+/// Returns the base64 encoded SHA-256 hash of the public key of a certificate.
+pub(crate) fn spki_hash(certificate: &[u8]) -> Option<String> {
+    x509::spki(certificate).map(|spki| base64::encode(&Sha256::digest(spki)))
+}
+
+/// Returns the label and the decoded contents of all PEM blocks.
+fn pem_blocks(pem: &[u8]) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let text = wrap_error(str::from_utf8(pem))?;
+    let mut blocks = Vec::new();
+    let mut current: Option<(String, String)> = None;
+    for line in text.lines().map(str::trim) {
+        if line.starts_with("-----BEGIN ") && line.ends_with("-----") {
+            let label = &line["-----BEGIN ".len()..line.len() - "-----".len()];
+            current = Some((label.to_string(), String::new()));
+        } else if line.starts_with("-----END ") {
+            if let Some((label, contents)) = current.take() {
+                blocks.push((label, wrap_error(base64::decode(&contents))?));
+            }
+        } else if let Some((_, ref mut contents)) = current {
+            contents.push_str(line);
+        }
+    }
+    Ok(blocks)
+}
+
+fn invalid_pem(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
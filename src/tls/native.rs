@@ -0,0 +1,100 @@
+use std::io;
+use std::net::TcpStream;
+
+use native_tls::{self, HandshakeError, Protocol};
+
+use super::{IdentityKind, TlsConfig, TlsConnector, TlsStream, TlsVersion};
+use util::wrap_error;
+use Error;
+
+/// Opens TLS connections with the TLS library of the operating system.
+pub(crate) struct NativeTlsConnector {
+    inner: native_tls::TlsConnector,
+}
+
+impl NativeTlsConnector {
+    pub(crate) fn new(config: &TlsConfig) -> io::Result<NativeTlsConnector> {
+        let mut builder = native_tls::TlsConnector::builder();
+        for root in &config.roots {
+            let certificate = wrap_error(native_tls::Certificate::from_der(root.der()))?;
+            builder.add_root_certificate(certificate);
+        }
+        if let Some(ref identity) = config.identity {
+            let identity = match identity.inner {
+                IdentityKind::Pkcs12 {
+                    ref der,
+                    ref password,
+                } => native_tls::Identity::from_pkcs12(der, password),
+                IdentityKind::Pem {
+                    ref certificates,
+                    ref key,
+                } => native_tls::Identity::from_pkcs8(certificates, key),
+            };
+            builder.identity(wrap_error(identity)?);
+        }
+        if let Some(version) = config.min_version {
+            let protocol = match version {
+                TlsVersion::Tls10 => Protocol::Tlsv10,
+                TlsVersion::Tls11 => Protocol::Tlsv11,
+                TlsVersion::Tls12 => Protocol::Tlsv12,
+                TlsVersion::Tls13 | TlsVersion::__Nonexhaustive => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "minimum TLS version is not supported by native-tls",
+                    ))
+                }
+            };
+            builder.min_protocol_version(Some(protocol));
+        }
+        if !config.alpn_protocols.is_empty() {
+            let protocols: Vec<&str> = config.alpn_protocols.iter().map(|p| &p[..]).collect();
+            builder.request_alpns(&protocols);
+        }
+        if config.accept_invalid_certs {
+            builder.danger_accept_invalid_certs(true);
+            builder.danger_accept_invalid_hostnames(true);
+        }
+        Ok(NativeTlsConnector {
+            inner: wrap_error(builder.build())?,
+        })
+    }
+}
+
+impl TlsConnector for NativeTlsConnector {
+    fn connect(&self, name: &str, stream: TcpStream) -> io::Result<Box<dyn TlsStream>> {
+        match self.inner.connect(name, stream) {
+            Ok(stream) => Ok(Box::new(stream)),
+            Err(HandshakeError::Failure(err)) => wrap_error(Err(err)),
+            // The read or write timeout of the socket expired.
+            Err(HandshakeError::WouldBlock(_)) => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, Error::TimedOut))
+            }
+        }
+    }
+}
+
+impl TlsStream for native_tls::TlsStream<TcpStream> {
+    fn tcp(&self) -> &TcpStream {
+        self.get_ref()
+    }
+
+    fn peer_certificates(&self) -> Vec<Vec<u8>> {
+        // native-tls only exposes the leaf certificate.
+        match self.peer_certificate() {
+            Ok(Some(certificate)) => certificate.to_der().into_iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn version(&self) -> Option<TlsVersion> {
+        None
+    }
+
+    fn cipher_suite(&self) -> Option<String> {
+        None
+    }
+
+    fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.negotiated_alpn().ok().and_then(|protocol| protocol)
+    }
+}
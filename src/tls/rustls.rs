@@ -0,0 +1,130 @@
+use std::convert::TryFrom;
+use std::io;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use rustls::{self, ClientConfig, ClientConnection, OwnedTrustAnchor, ProtocolVersion,
+             RootCertStore, ServerName, StreamOwned, SupportedProtocolVersion};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use webpki_roots;
+
+use super::{TlsConfig, TlsConnector, TlsStream, TlsVersion};
+use util::{map_timeout, wrap_error};
+
+/// Opens TLS connections with rustls.
+pub(crate) struct RustlsConnector {
+    config: Arc<ClientConfig>,
+}
+
+impl RustlsConnector {
+    pub(crate) fn new(config: &TlsConfig) -> io::Result<RustlsConnector> {
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+        for root in &config.roots {
+            wrap_error(roots.add(&rustls::Certificate(root.der().to_vec())))?;
+        }
+        // rustls does not implement versions before TLS 1.2.
+        let versions: &[&'static SupportedProtocolVersion] = match config.min_version {
+            Some(TlsVersion::Tls13) => &[&rustls::version::TLS13],
+            _ => &[&rustls::version::TLS12, &rustls::version::TLS13],
+        };
+        let builder = wrap_error(
+            ClientConfig::builder()
+                .with_safe_default_cipher_suites()
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(versions),
+        )?.with_root_certificates(roots);
+        let mut client_config = if let Some(ref identity) = config.identity {
+            let (certificates, key) = identity.pem_parts()?;
+            let certificates = certificates.into_iter().map(rustls::Certificate).collect();
+            wrap_error(builder.with_client_auth_cert(certificates, rustls::PrivateKey(key)))?
+        } else {
+            builder.with_no_client_auth()
+        };
+        client_config.alpn_protocols = config
+            .alpn_protocols
+            .iter()
+            .map(|protocol| protocol.as_bytes().to_vec())
+            .collect();
+        if config.accept_invalid_certs {
+            client_config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoVerification));
+        }
+        Ok(RustlsConnector {
+            config: Arc::new(client_config),
+        })
+    }
+}
+
+impl TlsConnector for RustlsConnector {
+    fn connect(&self, name: &str, mut stream: TcpStream) -> io::Result<Box<dyn TlsStream>> {
+        let name = ServerName::try_from(name)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mut connection = wrap_error(ClientConnection::new(self.config.clone(), name))?;
+        // Complete the handshake so errors are reported here.
+        while connection.is_handshaking() {
+            match connection.complete_io(&mut stream) {
+                Ok(_) => {}
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(map_timeout(err)),
+            }
+        }
+        Ok(Box::new(StreamOwned::new(connection, stream)))
+    }
+}
+
+impl TlsStream for StreamOwned<ClientConnection, TcpStream> {
+    fn tcp(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    fn peer_certificates(&self) -> Vec<Vec<u8>> {
+        self.conn
+            .peer_certificates()
+            .map(|certificates| certificates.iter().map(|c| c.0.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    fn version(&self) -> Option<TlsVersion> {
+        match self.conn.protocol_version()? {
+            ProtocolVersion::TLSv1_2 => Some(TlsVersion::Tls12),
+            ProtocolVersion::TLSv1_3 => Some(TlsVersion::Tls13),
+            _ => None,
+        }
+    }
+
+    fn cipher_suite(&self) -> Option<String> {
+        self.conn
+            .negotiated_cipher_suite()
+            .map(|suite| format!("{:?}", suite.suite()))
+    }
+
+    fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.conn.alpn_protocol().map(|protocol| protocol.to_vec())
+    }
+}
+
+/// Accepts every server certificate.
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
@@ -0,0 +1,160 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use util::days_from_civil;
+
+const SEQUENCE: u8 = 0x30;
+const VERSION: u8 = 0xa0;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+
+/// Returns the DER encoded SubjectPublicKeyInfo of a certificate.
+// https://tools.ietf.org/html/rfc5280#section-4.1
+pub(crate) fn spki(certificate: &[u8]) -> Option<&[u8]> {
+    // serialNumber, signature, issuer, validity, subject
+    let tbs = tbs_field(certificate, 5)?;
+    let (_, element, _) = expect(tbs, SEQUENCE)?;
+    Some(element)
+}
+
+/// Returns the end of the validity period of a certificate.
+pub(crate) fn not_after(certificate: &[u8]) -> Option<SystemTime> {
+    // serialNumber, signature, issuer
+    let tbs = tbs_field(certificate, 3)?;
+    let (validity, _, _) = expect(tbs, SEQUENCE)?;
+    let (_, _, validity) = read_element(validity)?;
+    let (time, element, _) = read_element(validity)?;
+    let (year, rest) = match element[0] {
+        UTC_TIME => {
+            let year = i64::from(digits(time.get(..2)?)?);
+            (if year < 50 { 2000 + year } else { 1900 + year }, &time[2..])
+        }
+        GENERALIZED_TIME => (i64::from(digits(time.get(..4)?)?), &time[4..]),
+        _ => return None,
+    };
+    if rest.len() != 11 || rest[10] != b'Z' {
+        return None;
+    }
+    let number = |i: usize| digits(&rest[i..i + 2]);
+    let days = days_from_civil(year, number(0)?, number(2)?);
+    let secs = days * 86400 + i64::from(number(4)? * 3600 + number(6)? * 60 + number(8)?);
+    if secs < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(secs as u64))
+}
+
+/// Parses ASCII digits, other bytes including signs are rejected.
+fn digits(input: &[u8]) -> Option<u32> {
+    input.iter().try_fold(0, |number, &byte| {
+        if byte.is_ascii_digit() {
+            Some(number * 10 + u32::from(byte - b'0'))
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the TBSCertificate starting at the field after `skip` fields
+/// following the version.
+fn tbs_field(certificate: &[u8], skip: usize) -> Option<&[u8]> {
+    let (certificate, _, _) = expect(certificate, SEQUENCE)?;
+    let (tbs, _, _) = expect(certificate, SEQUENCE)?;
+    let mut tbs = skip_version(tbs);
+    for _ in 0..skip {
+        tbs = read_element(tbs)?.2;
+    }
+    Some(tbs)
+}
+
+/// Skips the optional explicitly tagged version at the start of a TBSCertificate.
+fn skip_version(input: &[u8]) -> &[u8] {
+    match expect(input, VERSION) {
+        Some((_, _, rest)) => rest,
+        None => input,
+    }
+}
+
+/// Reads an element with `tag` and returns its contents,
+/// the whole element and the remaining input.
+fn expect(input: &[u8], tag: u8) -> Option<(&[u8], &[u8], &[u8])> {
+    if input.first() != Some(&tag) {
+        return None;
+    }
+    read_element(input)
+}
+
+/// Returns the contents, the whole element and the remaining input.
+fn read_element(input: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let first = *input.get(1)?;
+    let (len, header) = if first < 0x80 {
+        (first as usize, 2)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let bytes = input.get(2..2 + count)?;
+        let len = bytes.iter().fold(0, |len, &b| len << 8 | b as usize);
+        (len, 2 + count)
+    };
+    let end = header.checked_add(len)?;
+    if end > input.len() {
+        return None;
+    }
+    Some((&input[header..end], &input[..end], &input[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut element = vec![tag, contents.len() as u8];
+        element.extend_from_slice(contents);
+        element
+    }
+
+    fn certificate(tag: u8, not_after: &[u8]) -> Vec<u8> {
+        let mut validity = element(UTC_TIME, b"130524000000Z");
+        validity.extend(element(tag, not_after));
+        let mut tbs = element(VERSION, &element(0x02, &[2]));
+        tbs.extend(element(0x02, &[1]));
+        tbs.extend(element(SEQUENCE, &[]));
+        tbs.extend(element(SEQUENCE, &[]));
+        tbs.extend(element(SEQUENCE, &validity));
+        tbs.extend(element(SEQUENCE, &[]));
+        tbs.extend(element(SEQUENCE, &[0x05, 0x00]));
+        element(SEQUENCE, &element(SEQUENCE, &tbs))
+    }
+
+    #[test]
+    fn reads_validity() {
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(1_369_353_600));
+        assert_eq!(not_after(&certificate(UTC_TIME, b"130524000000Z")), expected);
+        let time = b"20130524000000Z";
+        assert_eq!(not_after(&certificate(GENERALIZED_TIME, time)), expected);
+        assert_eq!(
+            not_after(&certificate(UTC_TIME, b"491231235959Z")),
+            Some(UNIX_EPOCH + Duration::from_secs(2_524_607_999))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        assert_eq!(not_after(&certificate(UTC_TIME, b"13052400000Z")), None);
+        assert_eq!(not_after(&certificate(UTC_TIME, b"+30524000000Z")), None);
+        assert_eq!(not_after(&certificate(UTC_TIME, b"1305240000+0Z")), None);
+        // Non-ASCII characters spanning the boundaries of fields.
+        let time = "130\u{e9}2400000Z";
+        assert_eq!(not_after(&certificate(UTC_TIME, time.as_bytes())), None);
+        let time = "201\u{e9}0524000000Z";
+        assert_eq!(not_after(&certificate(GENERALIZED_TIME, time.as_bytes())), None);
+        assert_eq!(not_after(&certificate(0x13, b"130524000000Z")), None);
+    }
+
+    #[test]
+    fn reads_public_key() {
+        let certificate = certificate(UTC_TIME, b"130524000000Z");
+        assert_eq!(spki(&certificate), Some(&[0x30, 0x02, 0x05, 0x00][..]));
+    }
+}
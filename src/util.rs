@@ -1,7 +1,12 @@
 use std::io;
+use std::time::{Duration, Instant};
 
-use http::{Method, StatusCode, Version};
-use http::header::{GetAll, HeaderValue};
+use base64;
+use encoding_rs::Encoding;
+use http::{Method, StatusCode, Url, Version};
+use http::header::{self, GetAll, HeaderMap, HeaderValue};
+
+use Error;
 
 pub(crate) fn is_redirect_status(status: StatusCode) -> bool {
     // https://fetch.spec.whatwg.org/#redirect-status
@@ -18,8 +23,37 @@ pub(crate) fn is_redirect_method_get(status: StatusCode, method: &Method) -> boo
         && method == &Method::POST || (status == StatusCode::SEE_OTHER)
 }
 
+/// Returns true if a request with the method can be retried, TRACE is excluded.
+pub(crate) fn is_idempotent(method: &Method) -> bool {
+    // https://httpwg.org/specs/rfc7231.html#idempotent.methods
+    *method == Method::GET || *method == Method::HEAD || *method == Method::PUT
+        || *method == Method::DELETE || *method == Method::OPTIONS
+}
+
+/// Returns true if the error shows the server closed a reused connection.
+pub(crate) fn is_closed_connection(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+pub(crate) fn remove_body_headers(headers: &mut HeaderMap) {
+    // https://fetch.spec.whatwg.org/#request-body-header-name
+    // The framing headers are set again when the request is sent.
+    headers.remove(header::CONTENT_ENCODING);
+    headers.remove(header::CONTENT_LANGUAGE);
+    headers.remove(header::CONTENT_LOCATION);
+    headers.remove(header::CONTENT_TYPE);
+    headers.remove(header::CONTENT_LENGTH);
+    headers.remove(header::TRANSFER_ENCODING);
+}
+
 pub(crate) fn is_chunked(values: GetAll<HeaderValue>) -> bool {
-    if let Some(last) = values.iter().last() {
+    if let Some(last) = values.iter().next_back() {
         if let Ok(s) = last.to_str() {
             return s.ends_with("chunked");
         }
@@ -65,6 +99,75 @@ pub(crate) fn get_content_length(values: GetAll<HeaderValue>) -> Option<usize> {
     result
 }
 
+pub(crate) fn get_charset(headers: &HeaderMap) -> Option<&'static Encoding> {
+    // https://httpwg.org/specs/rfc7231.html#media.type
+    let value = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+    for parameter in value.split(';').skip(1) {
+        let mut parts = parameter.splitn(2, '=');
+        let name = parts.next()?.trim();
+        if !name.eq_ignore_ascii_case("charset") {
+            continue;
+        }
+        let label = parts.next()?.trim().trim_matches('"');
+        return Encoding::for_label(label.as_bytes());
+    }
+    None
+}
+
+/// Returns the number of days since 1970-01-01 for a date
+/// in the proleptic Gregorian calendar.
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Returns the date in the proleptic Gregorian calendar
+/// for a number of days since 1970-01-01.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = (if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 }) as u32;
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// Returns the value of an `Authorization` header for basic authentication.
+pub(crate) fn basic_auth(username: &str, password: &str) -> HeaderValue {
+    // https://httpwg.org/specs/rfc7617.html
+    let credentials = base64::encode(format!("{}:{}", username, password).as_bytes());
+    HeaderValue::from_str(&format!("Basic {}", credentials)).expect("base64 is valid header")
+}
+
+/// Returns the lowercase hexadecimal representation of `bytes`.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns the host and port of a URL as used in the `CONNECT` method.
+pub(crate) fn host_and_port(url: &Url) -> io::Result<String> {
+    match (url.host_str(), url.port_or_known_default()) {
+        (Some(host), Some(port)) => Ok(format!("{}:{}", host, port)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "URL has no host or port",
+        )),
+    }
+}
+
 pub(crate) fn wrap_error<T, E>(result: Result<T, E>) -> io::Result<T>
 where
     E: 'static + ::std::error::Error + ::std::marker::Send + ::std::marker::Sync,
@@ -74,3 +177,34 @@ where
         Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
     }
 }
+
+pub(crate) fn is_timeout(err: &io::Error) -> bool {
+    // Socket timeouts are reported as `WouldBlock` on Unix and
+    // as `TimedOut` on Windows.
+    err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut
+}
+
+pub(crate) fn map_timeout(err: io::Error) -> io::Error {
+    if is_timeout(&err) {
+        io::Error::new(io::ErrorKind::TimedOut, Error::TimedOut)
+    } else {
+        err
+    }
+}
+
+pub(crate) fn remaining_timeout(
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+) -> io::Result<Option<Duration>> {
+    let deadline = if let Some(deadline) = deadline {
+        deadline
+    } else {
+        return Ok(timeout);
+    };
+    let now = Instant::now();
+    if now >= deadline {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, Error::TimedOut));
+    }
+    let remaining = deadline - now;
+    Ok(Some(timeout.map_or(remaining, |timeout| timeout.min(remaining))))
+}